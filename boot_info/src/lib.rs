@@ -0,0 +1,466 @@
+//! The boot protocol shared between the bootloader and the kernel
+//!
+//! The bootloader fills in a [`BootInfo`] and passes a pointer to it as the only argument
+//! of the kernel entry point. Both crates depend on this one so the layout can't drift apart,
+//! and the kernel checks the magic and version before reading anything else, in case it was
+//! started by an older or newer bootloader.
+#![no_std]
+use core::{fmt, mem, slice, str};
+
+/// "BLOGOSBI" read as a little endian integer, identifies a valid boot info struct
+pub const BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"BLOGOSBI");
+
+/// Has to be bumped every time the layout of [`BootInfo`], or anything inside of it, changes
+pub const BOOT_INFO_VERSION: u32 = 15;
+
+/// Everything the kernel gets from the bootloader
+///
+/// The first three fields are the header and must never change,
+/// so that mismatched versions can always be detected.
+/// All pointers in here are virtual addresses in the kernel's address space,
+/// except for the firmware tables
+#[repr(C)]
+pub struct BootInfo {
+    /// Always [`BOOT_INFO_MAGIC`]
+    pub magic: u64,
+    /// The [`BOOT_INFO_VERSION`] the bootloader was built with
+    pub version: u32,
+    /// Size of this struct in bytes, as seen by the bootloader
+    pub size: u32,
+    pub framebuffer: FramebufferInfo,
+    /// The UEFI memory map at the time boot services were exited
+    pub memory_map: MemoryMap,
+    /// Physical memory the kernel's loadable segments were copied into
+    pub kernel_image: PhysicalRange,
+    /// How far the kernel was moved from the addresses it was linked at, by KASLR
+    pub kernel_slide: u64,
+    /// All of physical memory is mapped starting at this virtual address
+    pub physical_memory_offset: u64,
+    /// Initial contents of every thread's thread-local storage, empty if the kernel has none
+    pub tls_template: TlsTemplate,
+    /// The kernel command line from the boot config, always UTF-8
+    pub cmdline: ByteSlice,
+    /// Physical memory holding the initial ramdisk, a tar archive, empty if there is none
+    pub initrd: PhysicalRange,
+    pub firmware_tables: FirmwareTables,
+    /// The stack `_start` runs on, it grows down from `end`
+    /// The page below `start` is never mapped, so overflowing the stack faults
+    pub kernel_stack: VirtualRange,
+    pub timestamps: BootTimestamps,
+    /// Everything the bootloader logged, always UTF-8
+    pub bootloader_log: ByteSlice,
+}
+
+impl BootInfo {
+    /// Create a boot info struct with the header filled in for the current version
+    /// and everything else empty, for the bootloader to fill in
+    pub fn new() -> Self {
+        Self {
+            magic: BOOT_INFO_MAGIC,
+            version: BOOT_INFO_VERSION,
+            size: mem::size_of::<Self>() as u32,
+            framebuffer: FramebufferInfo::empty(),
+            memory_map: MemoryMap::empty(),
+            kernel_image: PhysicalRange { start: 0, end: 0 },
+            kernel_slide: 0,
+            physical_memory_offset: 0,
+            tls_template: TlsTemplate::empty(),
+            cmdline: ByteSlice::empty(),
+            initrd: PhysicalRange { start: 0, end: 0 },
+            firmware_tables: FirmwareTables {
+                acpi_rsdp: 0,
+                smbios3_entry_point: 0,
+                system_table: 0,
+            },
+            kernel_stack: VirtualRange { start: 0, end: 0 },
+            timestamps: BootTimestamps::empty(),
+            bootloader_log: ByteSlice::empty(),
+        }
+    }
+
+    /// The kernel command line, empty if the bootloader didn't pass one
+    pub fn cmdline(&self) -> &str {
+        str::from_utf8(self.cmdline.as_bytes()).unwrap_or("")
+    }
+
+    /// The messages the bootloader logged, one per line
+    pub fn bootloader_log(&self) -> &str {
+        str::from_utf8(self.bootloader_log.as_bytes()).unwrap_or("")
+    }
+
+    /// Checks that the struct was created by a bootloader using the same version of the protocol
+    pub fn validate(&self) -> Result<(), BootInfoError> {
+        if self.magic != BOOT_INFO_MAGIC {
+            return Err(BootInfoError::BadMagic(self.magic));
+        }
+        if self.version != BOOT_INFO_VERSION {
+            return Err(BootInfoError::VersionMismatch {
+                bootloader: self.version,
+                kernel: BOOT_INFO_VERSION,
+            });
+        }
+        if self.size as usize != mem::size_of::<Self>() {
+            return Err(BootInfoError::SizeMismatch {
+                bootloader: self.size,
+                kernel: mem::size_of::<Self>() as u32,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for BootInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reasons why the kernel can't use the boot info it was given
+#[derive(Debug, Clone, Copy)]
+pub enum BootInfoError {
+    /// The pointer doesn't point to a boot info struct at all
+    BadMagic(u64),
+    /// The bootloader and kernel were built against different versions of this crate
+    VersionMismatch { bootloader: u32, kernel: u32 },
+    /// Same version but a different layout, which means someone forgot to bump the version
+    SizeMismatch { bootloader: u32, kernel: u32 },
+}
+
+impl fmt::Display for BootInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootInfoError::BadMagic(magic) => {
+                write!(f, "invalid boot info magic {:#x}", magic)
+            }
+            BootInfoError::VersionMismatch { bootloader, kernel } => write!(
+                f,
+                "boot info version mismatch: bootloader uses v{}, kernel expects v{}",
+                bootloader, kernel
+            ),
+            BootInfoError::SizeMismatch { bootloader, kernel } => write!(
+                f,
+                "boot info size mismatch: bootloader uses {} bytes, kernel expects {} bytes",
+                bootloader, kernel
+            ),
+        }
+    }
+}
+
+/// Contains all the necessary information to use the GOP framebuffer
+///
+/// Without a GOP, or when it only supports drawing through `Blt`, there is no linear
+/// framebuffer and this is [`FramebufferInfo::empty`]
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct FramebufferInfo {
+    /// Virtual address of the first pixel
+    pub base: u64,
+    /// Physical address of the first pixel
+    pub physical_base: u64,
+    /// Size of the framebuffer in bytes
+    pub size: u64,
+    pub width: u64,
+    pub height: u64,
+    /// Number of pixels per scanline, which can be larger than the width
+    pub stride: u64,
+    pub pixel_format: PixelFormat,
+    /// Which bits of a pixel hold each color, filled in for every format
+    pub masks: PixelMasks,
+}
+
+impl FramebufferInfo {
+    /// Every GOP pixel format uses 32 bits per pixel
+    pub const BYTES_PER_PIXEL: u64 = 4;
+
+    pub const fn empty() -> Self {
+        Self {
+            base: 0,
+            physical_base: 0,
+            size: 0,
+            width: 0,
+            height: 0,
+            stride: 0,
+            pixel_format: PixelFormat::Bgr,
+            masks: PixelMasks {
+                red: 0,
+                green: 0,
+                blue: 0,
+            },
+        }
+    }
+
+    /// Whether there is a linear framebuffer at all
+    pub fn is_present(&self) -> bool {
+        self.size != 0
+    }
+
+    /// Number of pixels that fit in the framebuffer
+    pub fn pixel_count(&self) -> u64 {
+        self.size / Self::BYTES_PER_PIXEL
+    }
+}
+
+/// How the colors of a pixel are laid out, the GOP formats that have a linear framebuffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PixelFormat {
+    /// Red in the lowest byte, then green and blue
+    Rgb,
+    /// Blue in the lowest byte, then green and red
+    Bgr,
+    /// Described by the masks
+    Bitmask,
+}
+
+/// Bits of a 32 bit pixel that belong to each color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct PixelMasks {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+}
+
+/// Physical addresses of the tables the firmware provides, 0 if a table doesn't exist
+///
+/// They can only be found through the UEFI configuration table,
+/// which the kernel has no way to get to on its own
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct FirmwareTables {
+    /// ACPI RSDP, revision 2 or newer if the firmware supports it, otherwise the ACPI 1.0 one
+    pub acpi_rsdp: u64,
+    /// SMBIOS 3 (64 bit) entry point structure
+    pub smbios3_entry_point: u64,
+    /// UEFI system table, only the runtime services can be used through it
+    pub system_table: u64,
+}
+
+/// Time stamp counter values the bootloader read at the main steps of booting,
+/// in the order they happen. Steps that weren't reached are 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct BootTimestamps {
+    /// First thing in the bootloader, everything before it is the firmware
+    pub bootloader_entry: u64,
+    /// The graphics mode was set, after the boot menu
+    pub gop_set: u64,
+    /// The kernel file was read, and decompressed if it was compressed
+    pub kernel_read: u64,
+    /// The kernel's segments were copied to where they run
+    pub segments_copied: u64,
+    /// Right after exiting boot services, shortly before jumping to the kernel
+    pub boot_services_exited: u64,
+}
+
+impl BootTimestamps {
+    pub const fn empty() -> Self {
+        Self {
+            bootloader_entry: 0,
+            gop_set: 0,
+            kernel_read: 0,
+            segments_copied: 0,
+            boot_services_exited: 0,
+        }
+    }
+}
+
+/// A range of physical addresses, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct PhysicalRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl PhysicalRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+}
+
+/// A range of virtual addresses, `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct VirtualRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl VirtualRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+}
+
+/// The kernel's PT_TLS segment
+///
+/// Each thread's thread-local storage is `mem_size` bytes, starting with a copy of the
+/// `file_size` bytes at `start` followed by zeros. It already is mapped as part of the kernel image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct TlsTemplate {
+    /// Virtual address of the initialized data
+    pub start: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+    /// Required alignment of each thread's block
+    pub align: u64,
+}
+
+impl TlsTemplate {
+    pub const fn empty() -> Self {
+        Self {
+            start: 0,
+            file_size: 0,
+            mem_size: 0,
+            align: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mem_size == 0
+    }
+}
+
+/// What a region of physical memory can be used for, simplified from the UEFI memory types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum MemoryRegionKind {
+    /// Free memory the kernel can use for anything
+    Usable,
+    /// Memory that must never be touched: firmware runtime services, ACPI NVS, MMIO, bad RAM
+    Reserved,
+    /// Holds ACPI tables, becomes usable once the kernel is done parsing them
+    AcpiReclaimable,
+    /// Used by the bootloader, including the boot info itself
+    Loader,
+    /// The kernel's loaded segments
+    Kernel,
+    /// Firmware boot services code and data, free after exiting boot services
+    /// This includes the firmware's stack, the kernel starts on its own stack in `Kernel` memory
+    BootServices,
+    /// The initial ramdisk
+    Initrd,
+}
+
+/// A range of physical memory with the same kind
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct MemoryRegion {
+    /// Physical address of the first byte, always page aligned
+    pub start: u64,
+    /// Number of 4KiB pages in the region
+    pub page_count: u64,
+    pub kind: MemoryRegionKind,
+}
+
+impl MemoryRegion {
+    pub const PAGE_SIZE: u64 = 4096;
+
+    /// A placeholder region, used to preallocate space for the memory map
+    pub const fn empty() -> Self {
+        Self {
+            start: 0,
+            page_count: 0,
+            kind: MemoryRegionKind::Reserved,
+        }
+    }
+
+    /// Physical address of the first byte after the region
+    pub fn end(&self) -> u64 {
+        self.start + self.page_count * Self::PAGE_SIZE
+    }
+}
+
+/// A list of memory regions sorted the way the firmware reported them
+///
+/// The regions live in memory allocated by the bootloader, as loader data
+#[derive(Debug)]
+#[repr(C)]
+pub struct MemoryMap {
+    regions: *const MemoryRegion,
+    len: u64,
+}
+
+// The regions are never written to after the kernel is started
+unsafe impl Send for MemoryMap {}
+unsafe impl Sync for MemoryMap {}
+
+impl MemoryMap {
+    pub const fn empty() -> Self {
+        Self {
+            regions: core::ptr::null(),
+            len: 0,
+        }
+    }
+
+    /// Creates the memory map from the virtual address of the first region
+    /// in the kernel's address space and the number of regions
+    ///
+    /// # Safety
+    /// The regions have to stay valid and unchanged for as long as the kernel runs
+    pub unsafe fn from_raw_parts(regions: u64, len: u64) -> Self {
+        Self {
+            regions: regions as *const MemoryRegion,
+            len,
+        }
+    }
+
+    pub fn regions(&self) -> &[MemoryRegion] {
+        if self.len == 0 {
+            return &[];
+        }
+        // The constructor requires the regions to live for as long as the kernel does
+        unsafe { slice::from_raw_parts(self.regions, self.len as usize) }
+    }
+}
+
+/// Some bytes the bootloader passes to the kernel, in memory allocated as loader data
+#[derive(Debug)]
+#[repr(C)]
+pub struct ByteSlice {
+    start: *const u8,
+    len: u64,
+}
+
+// The bytes are never written to after the kernel is started
+unsafe impl Send for ByteSlice {}
+unsafe impl Sync for ByteSlice {}
+
+impl ByteSlice {
+    pub const fn empty() -> Self {
+        Self {
+            start: core::ptr::null(),
+            len: 0,
+        }
+    }
+
+    /// Creates the slice from the virtual address of the first byte
+    /// in the kernel's address space and the number of bytes
+    ///
+    /// # Safety
+    /// The bytes have to stay valid and unchanged for as long as the kernel runs
+    pub unsafe fn from_raw_parts(start: u64, len: u64) -> Self {
+        Self {
+            start: start as *const u8,
+            len,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // The constructor requires the bytes to live for as long as the kernel does
+        unsafe { slice::from_raw_parts(self.start, self.len as usize) }
+    }
+}
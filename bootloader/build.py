@@ -4,6 +4,7 @@
 
 import argparse
 import filecmp
+import hashlib
 import json
 import os
 from pathlib import Path
@@ -39,6 +40,8 @@ SETTINGS = {
     # `find_ovmf` function will try to find one if this isn't specified.
     'ovmf_dir': None,
     'debug': False,
+    # Give the VM a network card and serve the ESP directory with QEMU's TFTP server
+    'tftp': False,
 }
 
 # Path to target directory. If None, it will be initialized with information
@@ -119,7 +122,12 @@ def build(*test_flags):
         output_file = boot_dir / 'BootAA64.efi'
 
     shutil.copy2(built_file, output_file)
-    shutil.copy2("../target/x86_64-blog_os/debug/blog_os.lf", esp_dir() / 'kernel.elf')
+    kernel_file = Path("../target/x86_64-blog_os/debug/blog_os.lf")
+    shutil.copy2(kernel_file, esp_dir() / 'kernel.elf')
+    # The digest of the original lets the bootloader notice a kernel that wasn't copied completely
+    digest = hashlib.sha256(kernel_file.read_bytes()).hexdigest()
+    (esp_dir() / 'kernel.elf.sha256').write_text(f'{digest}  kernel.elf\n')
+    shutil.copy2("boot.cfg", esp_dir() / 'boot.cfg')
 
 def clippy():
     'Runs Clippy on all projects'
@@ -256,6 +264,14 @@ def run_qemu():
             #'-debugcon', 'file:debug.log', '-global', 'isa-debugcon.iobase=0x402',
         ])
 
+    if SETTINGS['tftp']:
+        qemu_flags.extend([
+            # User mode networking answers DHCP itself, with 10.0.2.2 as the TFTP server
+            '-netdev', f'user,id=net0,tftp={esp_dir()}',
+            # An empty romfile leaves the card to OVMF's own driver and PXE support
+            '-device', 'virtio-net-pci,netdev=net0,romfile=',
+        ])
+
     # When running in headless mode we don't have video, but we can still have
     # QEMU emulate a display and take screenshots from it.
     qemu_flags.extend(['-vga', 'std'])
@@ -378,6 +394,9 @@ def main():
     parser.add_argument('--debug', '-d', help='enable gdb debugging',
                         action='store_true')
 
+    parser.add_argument('--tftp', help='serve the ESP over TFTP, for source=tftp in boot.cfg',
+                        action='store_true')
+
     opts = parser.parse_args()
 
     SETTINGS['arch'] = opts.target
@@ -387,6 +406,7 @@ def main():
     SETTINGS['config'] = 'release' if opts.release else 'debug'
     SETTINGS['ci'] = opts.ci
     SETTINGS['debug'] = opts.debug
+    SETTINGS['tftp'] = opts.tftp
 
     verb = opts.verb
 
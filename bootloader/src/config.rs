@@ -0,0 +1,296 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use log::{warn, LevelFilter};
+use uefi::prelude::*;
+
+use crate::fs;
+use crate::sha256::Digest;
+
+/// Path of the configuration file on the boot volume
+const CONFIG_PATH: &str = "boot.cfg";
+
+/// Settings read from `boot.cfg`, a list of `key=value` lines
+///
+/// Empty lines and lines starting with `#` are ignored. Missing or invalid settings
+/// keep their default, so the bootloader still works without a config file.
+/// `entry=<title>` starts a boot menu entry, the `kernel`, `initrd`, `cmdline` and digest lines
+/// after it only apply to that entry. Before the first entry they set the defaults
+/// for all entries, and without any entries there is a single one using the defaults
+#[derive(Debug, Clone)]
+pub struct BootConfig {
+    /// The boot menu, never empty
+    pub entries: Vec<BootEntry>,
+    /// Index of the entry that is booted when the timeout runs out,
+    /// `default=<index or title>`
+    pub default_entry: usize,
+    /// Seconds the menu waits before booting the default entry, 0 skips the menu,
+    /// `timeout=5`
+    pub timeout: u64,
+    /// Which bootloader messages are shown, from `off` to `trace`, `verbosity=info`
+    pub verbosity: LevelFilter,
+    /// Preferred screen resolution as width and height, `resolution=1600x900`
+    pub resolution: Option<(usize, usize)>,
+    /// What to use when the preferred resolution isn't available, `resolution_fallback=largest`
+    pub resolution_fallback: ResolutionFallback,
+    /// Size of the kernel's stack in bytes, set in KiB and rounded up to whole pages,
+    /// `stack_size=128`
+    pub stack_size: u64,
+    /// Where the kernel and initrd are read from, `source=esp`
+    pub source: FileSource,
+    /// IPv4 address of the TFTP server, `tftp_server=10.0.2.2`
+    /// Without one the boot server from the DHCP reply is used
+    pub tftp_server: Option<[u8; 4]>,
+}
+
+/// What to boot, one line of the boot menu
+#[derive(Debug, Clone)]
+pub struct BootEntry {
+    pub title: String,
+    /// Path of the kernel on the boot volume, `kernel=kernel.elf`
+    /// It can be compressed with gzip or LZ4, also as `kernel.elf.gz` or `kernel.elf.lz4`
+    pub kernel_path: String,
+    /// Path of the initial ramdisk, a tar archive, `initrd=initrd.tar`
+    /// Booting continues without one if the file doesn't exist
+    pub initrd_path: String,
+    /// Passed to the kernel as is, `cmdline=console=serial loglevel=debug`
+    pub cmdline: String,
+    /// Expected SHA-256 of the kernel file as 64 hex digits, `kernel_sha256=<digest>`
+    /// Without one a detached `<kernel>.sha256` file is used if there is one,
+    /// named after the file that was actually read, like `kernel.elf.gz.sha256`
+    pub kernel_sha256: Option<Digest>,
+    /// Expected SHA-256 of the initrd, `initrd_sha256=<digest>`
+    pub initrd_sha256: Option<Digest>,
+}
+
+impl Default for BootEntry {
+    fn default() -> Self {
+        Self {
+            title: "blog_os".to_string(),
+            kernel_path: "kernel.elf".to_string(),
+            initrd_path: "initrd.tar".to_string(),
+            cmdline: String::new(),
+            kernel_sha256: None,
+            initrd_sha256: None,
+        }
+    }
+}
+
+/// Where the kernel and initrd come from, `boot.cfg` itself is always on the boot volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSource {
+    /// The boot volume, usually the EFI system partition
+    Esp,
+    /// A TFTP server, through the firmware's PXE support. Files that can't be fetched
+    /// are read from the boot volume instead
+    Tftp,
+}
+
+/// How a graphics mode is picked when the preferred resolution can't be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolutionFallback {
+    /// The mode with the most pixels
+    Largest,
+    /// The mode closest in size to the preferred resolution
+    Closest,
+    /// The monitor's preferred resolution from its EDID, or the closest mode to it
+    Native,
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            entries: vec![BootEntry::default()],
+            default_entry: 0,
+            timeout: 0,
+            verbosity: LevelFilter::Info,
+            resolution: None,
+            resolution_fallback: ResolutionFallback::Native,
+            stack_size: 128 * 1024,
+            source: FileSource::Esp,
+            tftp_server: None,
+        }
+    }
+}
+
+impl BootConfig {
+    /// Reads the config file from the boot volume, or uses the defaults if there is none
+    pub fn load(bs: &BootServices) -> Self {
+        match fs::read_file(bs, CONFIG_PATH) {
+            Ok(data) => match core::str::from_utf8(&data) {
+                Ok(text) => Self::parse(text),
+                Err(_) => {
+                    warn!("{} is not valid UTF-8, using the defaults", CONFIG_PATH);
+                    Self::default()
+                }
+            },
+            Err(Status::NOT_FOUND) => Self::default(),
+            Err(status) => {
+                warn!(
+                    "Can't read {}: {:?}, using the defaults",
+                    CONFIG_PATH, status
+                );
+                Self::default()
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self::default();
+        let mut parser = Parser {
+            defaults: BootEntry::default(),
+            entries: Vec::new(),
+            default_entry: None,
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => {
+                    warn!("{}:{}: expected key=value", CONFIG_PATH, number + 1);
+                    continue;
+                }
+            };
+            if !parser.set_entry(key, value) && !config.set(key, value) {
+                warn!(
+                    "{}:{}: invalid setting {}={}",
+                    CONFIG_PATH,
+                    number + 1,
+                    key,
+                    value
+                );
+            }
+        }
+
+        if !parser.entries.is_empty() {
+            config.entries = parser.entries;
+        } else {
+            config.entries = vec![parser.defaults];
+        }
+        if let Some(default) = parser.default_entry {
+            let index = match default.parse::<usize>() {
+                Ok(index) => Some(index).filter(|&index| index < config.entries.len()),
+                Err(_) => config
+                    .entries
+                    .iter()
+                    .position(|entry| entry.title == default),
+            };
+            match index {
+                Some(index) => config.default_entry = index,
+                None => warn!("{}: there is no entry {}", CONFIG_PATH, default),
+            }
+        }
+        config
+    }
+
+    /// Applies one global setting, returns false if the key or value isn't valid
+    fn set(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "timeout" => match value.parse() {
+                Ok(timeout) => self.timeout = timeout,
+                Err(_) => return false,
+            },
+            "verbosity" => match value.parse() {
+                Ok(level) => self.verbosity = level,
+                Err(_) => return false,
+            },
+            "resolution" => match parse_resolution(value) {
+                Some(resolution) => self.resolution = Some(resolution),
+                None => return false,
+            },
+            "resolution_fallback" => {
+                self.resolution_fallback = match value {
+                    "largest" => ResolutionFallback::Largest,
+                    "closest" => ResolutionFallback::Closest,
+                    "native" => ResolutionFallback::Native,
+                    _ => return false,
+                }
+            }
+            "stack_size" => match value
+                .parse::<u64>()
+                .ok()
+                .and_then(|kib| kib.checked_mul(1024))
+                .and_then(|size| size.checked_add(0xFFF))
+            {
+                Some(size) if size > 0xFFF => self.stack_size = size & !0xFFF,
+                _ => return false,
+            },
+            "source" => {
+                self.source = match value {
+                    "esp" => FileSource::Esp,
+                    "tftp" => FileSource::Tftp,
+                    _ => return false,
+                }
+            }
+            "tftp_server" => match parse_ipv4(value) {
+                Some(address) => self.tftp_server = Some(address),
+                None => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Keeps track of the boot entries while parsing
+struct Parser {
+    /// What new entries start out with
+    defaults: BootEntry,
+    entries: Vec<BootEntry>,
+    /// The value of `default`, which can refer to an entry that comes after it
+    default_entry: Option<String>,
+}
+
+impl Parser {
+    /// Applies one setting that is about the boot entries, returns false for any other key
+    fn set_entry(&mut self, key: &str, value: &str) -> bool {
+        if key == "entry" {
+            let mut entry = self.defaults.clone();
+            entry.title = value.to_string();
+            self.entries.push(entry);
+            return true;
+        }
+        if key == "default" {
+            self.default_entry = Some(value.to_string());
+            return true;
+        }
+        // Settings before the first entry are the defaults
+        let entry = self.entries.last_mut().unwrap_or(&mut self.defaults);
+        match key {
+            "kernel" if !value.is_empty() => entry.kernel_path = value.to_string(),
+            "initrd" if !value.is_empty() => entry.initrd_path = value.to_string(),
+            "cmdline" => entry.cmdline = value.to_string(),
+            "kernel_sha256" => match Digest::parse(value) {
+                Some(digest) => entry.kernel_sha256 = Some(digest),
+                None => return false,
+            },
+            "initrd_sha256" => match Digest::parse(value) {
+                Some(digest) => entry.initrd_sha256 = Some(digest),
+                None => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Parses a resolution written as `<width>x<height>`
+fn parse_resolution(value: &str) -> Option<(usize, usize)> {
+    let (width, height) = value.split_once('x')?;
+    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+}
+
+/// Parses an IPv4 address written as `a.b.c.d`
+fn parse_ipv4(value: &str) -> Option<[u8; 4]> {
+    let mut address = [0; 4];
+    let mut parts = value.split('.');
+    for byte in address.iter_mut() {
+        *byte = parts.next()?.parse().ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(address),
+    }
+}
@@ -0,0 +1,364 @@
+//! DEFLATE and the gzip container around it, as described in RFC 1951 and RFC 1952
+//!
+//! This follows the structure of zlib's `puff.c`, which favors simplicity over speed
+
+use alloc::vec::Vec;
+
+use super::{copy_match, reserved_size, DecompressError};
+
+/// The longest Huffman code DEFLATE allows
+const MAX_BITS: usize = 15;
+
+/// Base lengths and extra bits for the length symbols 257 to 285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances and extra bits for the distance symbols 0 to 29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which the code length code lengths of a dynamic block are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+// gzip header flags
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+/// Decompresses a gzip file with a single member and checks its CRC-32 and size
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    if data.len() < 18 || data[0..2] != [0x1F, 0x8B] {
+        return Err(DecompressError::BadGzipHeader);
+    }
+    if data[2] != 8 {
+        return Err(DecompressError::UnsupportedGzipMethod(data[2]));
+    }
+    let flags = data[3];
+    if flags & 0xE0 != 0 {
+        return Err(DecompressError::BadGzipHeader);
+    }
+    // Skip the modification time, extra flags and OS
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = read_u16(data, pos)? as usize;
+        pos += 2 + len;
+    }
+    // The file name and comment are zero terminated
+    for flag in [FNAME, FCOMMENT].iter() {
+        if flags & flag != 0 {
+            let rest = data.get(pos..).ok_or(DecompressError::UnexpectedEnd)?;
+            let len = rest
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or(DecompressError::UnexpectedEnd)?;
+            pos += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    // The size in the trailer is only the low 32 bits, but it's right for anything that fits
+    // into memory here. It's only checked after decompressing, so it's just a hint until then
+    let size = read_u32(data, data.len() - 4)?;
+    let mut output = Vec::with_capacity(reserved_size(size as u64, data.len()));
+    let compressed = data.get(pos..).ok_or(DecompressError::UnexpectedEnd)?;
+    let end = pos + inflate(compressed, &mut output)?;
+
+    let crc = read_u32(data, end)?;
+    let size = read_u32(data, end + 4)?;
+    if crc32(&output) != crc {
+        return Err(DecompressError::GzipChecksumMismatch);
+    }
+    if output.len() as u32 != size {
+        return Err(DecompressError::GzipSizeMismatch);
+    }
+    Ok(output)
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, DecompressError> {
+    let bytes = data
+        .get(pos..pos + 2)
+        .ok_or(DecompressError::UnexpectedEnd)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, DecompressError> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or(DecompressError::UnexpectedEnd)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Decompresses a raw DEFLATE stream, appending to `output`
+/// Returns how many bytes of `data` the stream took up
+fn inflate(data: &[u8], output: &mut Vec<u8>) -> Result<usize, DecompressError> {
+    let mut bits = BitReader {
+        data,
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => stored_block(&mut bits, output)?,
+            1 => {
+                let (lengths, distances) = fixed_codes();
+                compressed_block(&mut bits, output, &lengths, &distances)?
+            }
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut bits)?;
+                compressed_block(&mut bits, output, &lengths, &distances)?
+            }
+            _ => return Err(DecompressError::BadBlockType),
+        }
+        if last {
+            return Ok(bits.pos);
+        }
+    }
+}
+
+/// Reads bits starting with the least significant bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Index of the next byte that isn't in the buffer yet
+    pos: usize,
+    buffer: u32,
+    /// Number of bits in the buffer
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, count: u32) -> Result<u32, DecompressError> {
+        while self.count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or(DecompressError::UnexpectedEnd)?;
+            self.buffer |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Drops the rest of the current byte, bytes are only ever read one at a time
+    /// so nothing past it is in the buffer
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+fn stored_block(bits: &mut BitReader, output: &mut Vec<u8>) -> Result<(), DecompressError> {
+    bits.align();
+    let header = bits
+        .data
+        .get(bits.pos..bits.pos + 4)
+        .ok_or(DecompressError::UnexpectedEnd)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let complement = u16::from_le_bytes([header[2], header[3]]);
+    if len != !complement {
+        return Err(DecompressError::BadStoredLength);
+    }
+    let start = bits.pos + 4;
+    let stored = bits
+        .data
+        .get(start..start + len as usize)
+        .ok_or(DecompressError::UnexpectedEnd)?;
+    output.extend_from_slice(stored);
+    bits.pos = start + len as usize;
+    Ok(())
+}
+
+/// A canonical Huffman code, stored as the number of codes of each length
+/// and the symbols ordered by their code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from the code length of every symbol, 0 meaning the symbol isn't used
+    ///
+    /// Incomplete codes are allowed since a distance code can have a single symbol,
+    /// decoding fails if the data uses a code that doesn't exist
+    fn new(lengths: &[u8]) -> Result<Self, DecompressError> {
+        let mut counts = [0; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(DecompressError::BadHuffmanCode);
+            }
+        }
+
+        // Where the symbols of each length start in the sorted table
+        let mut offsets = [0; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    /// Reads one code bit by bit, codes are stored starting with their most significant bit
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, DecompressError> {
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+        for len in 1..=MAX_BITS {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(DecompressError::BadHuffmanCode)
+    }
+}
+
+/// The codes blocks of type 1 use
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    lengths[0..144].iter_mut().for_each(|len| *len = 8);
+    lengths[144..256].iter_mut().for_each(|len| *len = 9);
+    lengths[256..280].iter_mut().for_each(|len| *len = 7);
+    lengths[280..288].iter_mut().for_each(|len| *len = 8);
+    // Both are complete codes
+    (
+        Huffman::new(&lengths).unwrap(),
+        Huffman::new(&[5; 30]).unwrap(),
+    )
+}
+
+/// Reads the codes at the start of a block of type 2
+fn dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), DecompressError> {
+    let length_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_length_count = bits.read(4)? as usize + 4;
+    if length_count > 286 || distance_count > 30 {
+        return Err(DecompressError::BadHuffmanCode);
+    }
+
+    let mut code_lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = bits.read(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    // The literal/length and distance code lengths are one sequence, repeats can cross over
+    let mut lengths = Vec::with_capacity(length_count + distance_count);
+    while lengths.len() < length_count + distance_count {
+        let symbol = code_length_code.decode(bits)?;
+        let (len, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or(DecompressError::BadHuffmanCode)?;
+                (previous, 3 + bits.read(2)?)
+            }
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        if lengths.len() + repeat as usize > length_count + distance_count {
+            return Err(DecompressError::BadHuffmanCode);
+        }
+        lengths.extend((0..repeat).map(|_| len));
+    }
+    // Without an end of block code the block could never end
+    if lengths[256] == 0 {
+        return Err(DecompressError::BadHuffmanCode);
+    }
+    Ok((
+        Huffman::new(&lengths[..length_count])?,
+        Huffman::new(&lengths[length_count..])?,
+    ))
+}
+
+fn compressed_block(
+    bits: &mut BitReader,
+    output: &mut Vec<u8>,
+    lengths: &Huffman,
+    distances: &Huffman,
+) -> Result<(), DecompressError> {
+    loop {
+        let symbol = lengths.decode(bits)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(DecompressError::BadHuffmanCode);
+                }
+                let len = LENGTH_BASE[index] as u32 + bits.read(LENGTH_EXTRA[index] as u32)?;
+                let index = distances.decode(bits)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err(DecompressError::BadHuffmanCode);
+                }
+                let distance =
+                    DISTANCE_BASE[index] as u32 + bits.read(DISTANCE_EXTRA[index] as u32)?;
+                copy_match(output, distance as usize, len as usize)?;
+            }
+        }
+    }
+}
+
+/// The CRC-32 gzip uses, with the reflected polynomial 0xEDB88320
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
@@ -0,0 +1,209 @@
+//! The LZ4 frame format and the block format inside of it
+//!
+//! Only the content checksum is verified, which covers the block checksums as well
+
+use alloc::vec::Vec;
+
+use super::{copy_match, reserved_size, DecompressError};
+
+pub const FRAME_MAGIC: u32 = 0x184D_2204;
+/// Skippable frames have magic numbers from 0x184D2A50 to 0x184D2A5F
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+// Frame descriptor flags
+const BLOCK_CHECKSUM: u8 = 1 << 4;
+const CONTENT_SIZE: u8 = 1 << 3;
+const CONTENT_CHECKSUM: u8 = 1 << 2;
+const DICTIONARY_ID: u8 = 1 << 0;
+
+// xxHash32 primes
+const PRIME1: u32 = 2_654_435_761;
+const PRIME2: u32 = 2_246_822_519;
+const PRIME3: u32 = 3_266_489_917;
+const PRIME4: u32 = 668_265_263;
+const PRIME5: u32 = 374_761_393;
+
+/// The highest bit of a block size marks a block that is stored uncompressed
+const UNCOMPRESSED: u32 = 1 << 31;
+
+/// Decompresses all frames in the data one after another
+pub fn decompress_frames(data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let mut input = Input { data, pos: 0 };
+    let mut output = Vec::new();
+    while input.pos < data.len() {
+        let magic = input.u32()?;
+        if magic & 0xFFFF_FFF0 == SKIPPABLE_MAGIC {
+            let size = input.u32()?;
+            input.bytes(size as usize)?;
+        } else if magic == FRAME_MAGIC {
+            frame(&mut input, &mut output)?;
+        } else {
+            return Err(DecompressError::BadLz4Magic(magic));
+        }
+    }
+    Ok(output)
+}
+
+/// Decompresses one frame, starting after its magic number
+fn frame(input: &mut Input, output: &mut Vec<u8>) -> Result<(), DecompressError> {
+    let flags = input.u8()?;
+    let version = flags >> 6;
+    if version != 1 {
+        return Err(DecompressError::UnsupportedLz4Version(version));
+    }
+    // The maximum block size only matters for streaming
+    input.u8()?;
+    if flags & CONTENT_SIZE != 0 {
+        let size = input.u32()? as u64 | (input.u32()? as u64) << 32;
+        output.reserve(reserved_size(size, input.data.len()));
+    }
+    if flags & DICTIONARY_ID != 0 {
+        return Err(DecompressError::Lz4Dictionary);
+    }
+    // Header checksum
+    input.u8()?;
+    let start = output.len();
+
+    loop {
+        let size = input.u32()?;
+        if size == 0 {
+            break;
+        }
+        if size & UNCOMPRESSED != 0 {
+            output.extend_from_slice(input.bytes((size & !UNCOMPRESSED) as usize)?);
+        } else {
+            block(input.bytes(size as usize)?, output)?;
+        }
+        if flags & BLOCK_CHECKSUM != 0 {
+            input.u32()?;
+        }
+    }
+    if flags & CONTENT_CHECKSUM != 0 && input.u32()? != xxh32(&output[start..]) {
+        return Err(DecompressError::Lz4ChecksumMismatch);
+    }
+    Ok(())
+}
+
+/// Decompresses one block, a sequence of literals each followed by a match,
+/// except for the last one
+///
+/// Matches can reach back into earlier blocks, which is how linked blocks work
+fn block(data: &[u8], output: &mut Vec<u8>) -> Result<(), DecompressError> {
+    let mut input = Input { data, pos: 0 };
+    loop {
+        let token = input.u8()?;
+        let literal_len = input.length(token >> 4)?;
+        output.extend_from_slice(input.bytes(literal_len)?);
+        if input.pos == data.len() {
+            return Ok(());
+        }
+        let offset = input.u16()?;
+        let match_len = input.length(token & 0xF)? + 4;
+        copy_match(output, offset as usize, match_len)?;
+    }
+}
+
+/// xxHash32 with a seed of 0, the checksum LZ4 frames use
+fn xxh32(data: &[u8]) -> u32 {
+    let round = |acc: u32, lane: &[u8]| {
+        let lane = u32::from_le_bytes([lane[0], lane[1], lane[2], lane[3]]);
+        acc.wrapping_add(lane.wrapping_mul(PRIME2))
+            .rotate_left(13)
+            .wrapping_mul(PRIME1)
+    };
+
+    let mut stripes = data.chunks_exact(16);
+    let mut hash = if data.len() >= 16 {
+        let mut acc = [
+            PRIME1.wrapping_add(PRIME2),
+            PRIME2,
+            0,
+            0u32.wrapping_sub(PRIME1),
+        ];
+        for stripe in &mut stripes {
+            for (acc, lane) in acc.iter_mut().zip(stripe.chunks_exact(4)) {
+                *acc = round(*acc, lane);
+            }
+        }
+        acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18))
+    } else {
+        PRIME5
+    };
+    hash = hash.wrapping_add(data.len() as u32);
+
+    let mut words = stripes.remainder().chunks_exact(4);
+    for word in &mut words {
+        let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        hash = hash
+            .wrapping_add(word.wrapping_mul(PRIME3))
+            .rotate_left(17)
+            .wrapping_mul(PRIME4);
+    }
+    for &byte in words.remainder() {
+        hash = hash
+            .wrapping_add((byte as u32).wrapping_mul(PRIME5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(PRIME2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(PRIME3);
+    hash ^ (hash >> 16)
+}
+
+/// Reads little endian values from a byte slice
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecompressError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or(DecompressError::UnexpectedEnd)?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(DecompressError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecompressError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecompressError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecompressError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a literal or match length whose 4 bit part from the token is given,
+    /// 15 means it continues in the following bytes until one isn't 255
+    fn length(&mut self, nibble: u8) -> Result<usize, DecompressError> {
+        let mut len = nibble as usize;
+        if nibble == 15 {
+            loop {
+                let byte = self.u8()?;
+                len += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,114 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+mod inflate;
+mod lz4;
+
+/// Compressed file formats the kernel can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    /// The LZ4 frame format written by the `lz4` tool
+    Lz4,
+}
+
+/// Recognizes compressed data by its magic number
+pub fn compression(data: &[u8]) -> Option<Compression> {
+    if data.starts_with(&[0x1F, 0x8B]) {
+        Some(Compression::Gzip)
+    } else if data.starts_with(&lz4::FRAME_MAGIC.to_le_bytes()) {
+        Some(Compression::Lz4)
+    } else {
+        None
+    }
+}
+
+/// Decompresses a whole gzip or LZ4 file into a new buffer
+pub fn decompress(compression: Compression, data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    match compression {
+        Compression::Gzip => inflate::gunzip(data),
+        Compression::Lz4 => lz4::decompress_frames(data),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressError {
+    /// The data ends in the middle of the compressed stream
+    UnexpectedEnd,
+    BadGzipHeader,
+    /// Only DEFLATE is supported as the gzip compression method
+    UnsupportedGzipMethod(u8),
+    /// DEFLATE block type 3 is reserved
+    BadBlockType,
+    /// The length of a stored block doesn't match its complement
+    BadStoredLength,
+    /// The Huffman code lengths don't describe a valid code, or the data uses a code that
+    /// doesn't exist
+    BadHuffmanCode,
+    /// A match refers to data before the start of the output
+    BadDistance,
+    /// The CRC-32 in the gzip trailer doesn't match the decompressed data
+    GzipChecksumMismatch,
+    /// The size in the gzip trailer doesn't match the decompressed data
+    GzipSizeMismatch,
+    /// Only version 1 of the LZ4 frame format exists
+    UnsupportedLz4Version(u8),
+    /// Frames compressed with a dictionary can't be decompressed without it
+    Lz4Dictionary,
+    /// The content checksum of an LZ4 frame doesn't match the decompressed data
+    Lz4ChecksumMismatch,
+    /// A frame or skippable frame doesn't start with a known magic number
+    BadLz4Magic(u32),
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecompressError::UnexpectedEnd => write!(f, "compressed data ends unexpectedly"),
+            DecompressError::BadGzipHeader => write!(f, "invalid gzip header"),
+            DecompressError::UnsupportedGzipMethod(method) => {
+                write!(f, "unsupported gzip compression method {}", method)
+            }
+            DecompressError::BadBlockType => write!(f, "invalid DEFLATE block type"),
+            DecompressError::BadStoredLength => write!(f, "invalid stored block length"),
+            DecompressError::BadHuffmanCode => write!(f, "invalid Huffman code"),
+            DecompressError::BadDistance => write!(f, "match distance is too far back"),
+            DecompressError::GzipChecksumMismatch => write!(f, "gzip CRC-32 doesn't match"),
+            DecompressError::GzipSizeMismatch => write!(f, "gzip size doesn't match"),
+            DecompressError::UnsupportedLz4Version(version) => {
+                write!(f, "unsupported LZ4 frame version {}", version)
+            }
+            DecompressError::Lz4Dictionary => write!(f, "LZ4 dictionaries are not supported"),
+            DecompressError::Lz4ChecksumMismatch => write!(f, "LZ4 checksum doesn't match"),
+            DecompressError::BadLz4Magic(magic) => write!(f, "invalid LZ4 magic {:#x}", magic),
+        }
+    }
+}
+
+/// How much of a decompressed size from a header to reserve up front, at most this many
+/// times the size of the compressed data. The header isn't verified before decompressing,
+/// so the size is only a hint and the output grows past this if it has to
+const MAX_RESERVED_RATIO: usize = 8;
+
+/// How many bytes to reserve for output that a header claims is `size` bytes long
+fn reserved_size(size: u64, compressed_len: usize) -> usize {
+    size.min(compressed_len.saturating_mul(MAX_RESERVED_RATIO) as u64) as usize
+}
+
+/// Copies `len` bytes that start `distance` bytes before the end of the output to its end,
+/// the two can overlap to repeat a short sequence
+fn copy_match(output: &mut Vec<u8>, distance: usize, len: usize) -> Result<(), DecompressError> {
+    if distance == 0 || distance > output.len() {
+        return Err(DecompressError::BadDistance);
+    }
+    let start = output.len() - distance;
+    if distance >= len {
+        output.extend_from_within(start..start + len);
+    } else {
+        for i in 0..len {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,384 @@
-pub fn read_elf_header<'a>(file: &[u8]) -> &'a ELFHeader {
-    unsafe { &*(file.as_ptr() as *const ELFHeader) }
+use core::{fmt, mem, ptr};
+
+/// "\x7FELF" read as a little endian integer
+const ELF_MAGIC: u32 = 0x464C_457F;
+/// 64 bit objects
+const CLASS_64: u8 = 2;
+/// Little endian
+const DATA_LITTLE_ENDIAN: u8 = 1;
+/// Executable file
+const TYPE_EXECUTABLE: u16 = 2;
+/// Shared object, which is what position independent executables are
+const TYPE_SHARED: u16 = 3;
+/// AMD x86-64
+const MACHINE_X86_64: u16 = 0x3E;
+
+/// Segment that should be loaded into memory
+pub const PT_LOAD: u32 = 1;
+/// Dynamic linking information
+pub const PT_DYNAMIC: u32 = 2;
+/// Path of the program interpreter (dynamic linker)
+pub const PT_INTERP: u32 = 3;
+/// Template for the thread-local storage of every thread
+pub const PT_TLS: u32 = 7;
+/// Permissions of the stack, GNU extension
+pub const PT_GNU_STACK: u32 = 0x6474_E551;
+
+/// Dynamic section entry tags
+const DT_NULL: i64 = 0;
+const DT_NEEDED: i64 = 1;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_RELAENT: i64 = 9;
+const DT_REL: i64 = 17;
+const DT_JMPREL: i64 = 23;
+const DT_RELR: i64 = 36;
+
+/// Relocation that adds the load offset to the addend
+pub const R_X86_64_RELATIVE: u32 = 8;
+
+/// Segment flag bits, there is also PF_R but every mapped page on x86_64 is readable
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+
+/// A kernel ELF file that has been checked to be safe to read
+///
+/// Every program header and the file contents of every segment
+/// are known to be within the bounds of the file
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    header: ELFHeader,
+    /// File offset and number of entries of the relocation table
+    relocations: (u64, u64),
+}
+
+impl<'a> ElfFile<'a> {
+    /// Checks that the file is a 64 bit little endian x86_64 executable, either static or
+    /// position independent, and that every offset it contains points inside of it
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        let header: ELFHeader = read(data, 0).ok_or(ElfError::TooSmall)?;
+        let ident = header.e_ident;
+        let magic = ident.magic_num;
+        if magic != ELF_MAGIC {
+            return Err(ElfError::BadMagic(magic));
+        }
+        if ident.arch != CLASS_64 {
+            return Err(ElfError::UnsupportedClass(ident.arch));
+        }
+        if ident.endianness != DATA_LITTLE_ENDIAN {
+            return Err(ElfError::UnsupportedEndianness(ident.endianness));
+        }
+        let machine = header.e_machine;
+        if machine != MACHINE_X86_64 {
+            return Err(ElfError::UnsupportedMachine(machine));
+        }
+        let ty = header.e_type;
+        if ty != TYPE_EXECUTABLE && ty != TYPE_SHARED {
+            return Err(ElfError::UnsupportedType(ty));
+        }
+        if (header.e_phentsize as usize) < mem::size_of::<ProgramHeader>() {
+            return Err(ElfError::BadProgramHeaderSize(header.e_phentsize));
+        }
+        let table_size = header.e_phentsize as u64 * header.e_phnum as u64;
+        if !in_bounds(data, header.e_phoff, table_size) {
+            return Err(ElfError::ProgramHeadersOutOfBounds);
+        }
+
+        let mut file = Self {
+            data,
+            header,
+            relocations: (0, 0),
+        };
+        for (index, segment) in file.program_headers().enumerate() {
+            if !in_bounds(data, segment.p_offset, segment.p_filesz) {
+                return Err(ElfError::SegmentOutOfBounds(index));
+            }
+            if segment.p_filesz > segment.p_memsz {
+                return Err(ElfError::SegmentFileSizeTooLarge(index));
+            }
+            // Segments are mapped in whole pages, so the end rounded up to a page has to fit too
+            if segment
+                .p_vaddr
+                .checked_add(segment.p_memsz)
+                .and_then(|end| end.checked_add(0xFFF))
+                .is_none()
+            {
+                return Err(ElfError::SegmentAddressOverflow(index));
+            }
+            // 0 and 1 both mean the segment doesn't need to be aligned
+            if segment.p_align > 1 && !segment.p_align.is_power_of_two() {
+                return Err(ElfError::BadAlignment(index));
+            }
+            match segment.p_type {
+                PT_LOAD if segment.is_writable() && segment.is_executable() => {
+                    return Err(ElfError::WritableAndExecutable(index))
+                }
+                PT_GNU_STACK if segment.is_executable() => return Err(ElfError::ExecutableStack),
+                PT_INTERP => return Err(ElfError::Interpreter),
+                _ => {}
+            }
+        }
+        if file
+            .program_headers()
+            .filter(|h| h.p_type == PT_TLS)
+            .count()
+            > 1
+        {
+            return Err(ElfError::MultipleTlsSegments);
+        }
+        if file.loadable_segments().next().is_none() {
+            return Err(ElfError::NoLoadableSegments);
+        }
+        if let Some(dynamic) = file.program_headers().find(|h| h.p_type == PT_DYNAMIC) {
+            file.relocations = file.parse_dynamic(&dynamic)?;
+        }
+        for (index, rela) in file.relocations().enumerate() {
+            if rela.ty() != R_X86_64_RELATIVE {
+                return Err(ElfError::UnsupportedRelocation(rela.ty()));
+            }
+            let end = rela.r_offset.checked_add(8);
+            if !file.loadable_segments().any(|segment| {
+                rela.r_offset >= segment.p_vaddr
+                    && end.map_or(false, |end| end <= segment.p_vaddr + segment.p_memsz)
+            }) {
+                return Err(ElfError::RelocationOutOfBounds(index));
+            }
+        }
+        Ok(file)
+    }
+
+    /// Finds the relocation table in the dynamic section
+    /// and returns its file offset and number of entries
+    ///
+    /// The kernel is linked statically, so only relocations against itself are allowed
+    fn parse_dynamic(&self, dynamic: &ProgramHeader) -> Result<(u64, u64), ElfError> {
+        let mut rela = None;
+        let mut rela_size = 0;
+        let mut rela_entry_size = mem::size_of::<Rela>() as u64;
+        let entry_count = dynamic.p_filesz / mem::size_of::<Dyn>() as u64;
+        for i in 0..entry_count {
+            let offset = dynamic.p_offset + i * mem::size_of::<Dyn>() as u64;
+            // The segment was checked to be inside of the file
+            let entry: Dyn = read(self.data, offset).unwrap();
+            match entry.d_tag {
+                DT_NULL => break,
+                DT_NEEDED => return Err(ElfError::SharedLibraries),
+                DT_RELA => rela = Some(entry.d_val),
+                DT_RELASZ => rela_size = entry.d_val,
+                DT_RELAENT => rela_entry_size = entry.d_val,
+                DT_REL | DT_JMPREL | DT_RELR => {
+                    return Err(ElfError::UnsupportedDynamicTag(entry.d_tag))
+                }
+                _ => {}
+            }
+        }
+        let rela = match rela {
+            Some(rela) if rela_size > 0 => rela,
+            _ => return Ok((0, 0)),
+        };
+        if rela_entry_size != mem::size_of::<Rela>() as u64 {
+            return Err(ElfError::BadRelocationSize(rela_entry_size));
+        }
+        // The table is addressed by its virtual address, find where that is in the file
+        let offset = self
+            .loadable_segments()
+            .find(|segment| {
+                rela >= segment.p_vaddr
+                    && rela
+                        .checked_add(rela_size)
+                        .map_or(false, |end| end <= segment.p_vaddr + segment.p_filesz)
+            })
+            .map(|segment| segment.p_offset + (rela - segment.p_vaddr))
+            .ok_or(ElfError::RelocationsOutOfBounds)?;
+        Ok((offset, rela_size / rela_entry_size))
+    }
+
+    pub fn entry_point(&self) -> u64 {
+        self.header.e_entry
+    }
+
+    /// Whether the kernel can be loaded at any address, after applying its relocations
+    pub fn is_position_independent(&self) -> bool {
+        self.header.e_type == TYPE_SHARED
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + Clone + 'a {
+        let data = self.data;
+        let offset = self.header.e_phoff;
+        let entry_size = self.header.e_phentsize as u64;
+        (0..self.header.e_phnum as u64)
+            // The bounds were checked when parsing
+            .map(move |i| read(data, offset + i * entry_size).unwrap())
+    }
+
+    /// All the PT_LOAD segments, which have to be copied into memory
+    pub fn loadable_segments(&self) -> impl Iterator<Item = ProgramHeader> + Clone + 'a {
+        self.program_headers()
+            .filter(|header| header.p_type == PT_LOAD)
+    }
+
+    /// The entries of the relocation table, they are all `R_X86_64_RELATIVE`
+    /// and point inside of a loadable segment
+    pub fn relocations(&self) -> impl Iterator<Item = Rela> + 'a {
+        let data = self.data;
+        let (offset, count) = self.relocations;
+        (0..count)
+            // The bounds were checked when parsing
+            .map(move |i| read(data, offset + i * mem::size_of::<Rela>() as u64).unwrap())
+    }
+
+    /// The template every thread's thread-local storage is initialized from, if there is one
+    pub fn tls_segment(&self) -> Option<ProgramHeader> {
+        self.program_headers()
+            .find(|header| header.p_type == PT_TLS)
+    }
+
+    /// The part of the segment that is stored in the file
+    pub fn segment_data(&self, segment: &ProgramHeader) -> &'a [u8] {
+        let start = segment.p_offset as usize;
+        &self.data[start..start + segment.p_filesz as usize]
+    }
+}
+
+/// Reads a `T` from `offset` in the file, if it fits
+fn read<T: Copy>(data: &[u8], offset: u64) -> Option<T> {
+    if !in_bounds(data, offset, mem::size_of::<T>() as u64) {
+        return None;
+    }
+    // Nothing in the file is guaranteed to be aligned
+    Some(unsafe { ptr::read_unaligned(data.as_ptr().add(offset as usize) as *const T) })
+}
+
+/// Whether `size` bytes starting at `offset` are inside of the file
+fn in_bounds(data: &[u8], offset: u64, size: u64) -> bool {
+    match offset.checked_add(size) {
+        Some(end) => end <= data.len() as u64,
+        None => false,
+    }
+}
+
+/// Reasons why the kernel file can't be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file is smaller than the ELF header
+    TooSmall,
+    BadMagic(u32),
+    /// Only 64 bit files are supported
+    UnsupportedClass(u8),
+    /// Only little endian files are supported
+    UnsupportedEndianness(u8),
+    /// Only x86_64 files are supported
+    UnsupportedMachine(u16),
+    /// Only executables and position independent executables are supported
+    UnsupportedType(u16),
+    /// Program headers are smaller than the 64 bit program header struct
+    BadProgramHeaderSize(u16),
+    ProgramHeadersOutOfBounds,
+    /// The file contents of the segment with this index aren't inside the file
+    SegmentOutOfBounds(usize),
+    /// The segment with this index has more data in the file than in memory
+    SegmentFileSizeTooLarge(usize),
+    /// The segment with this index ends past the end of the address space
+    SegmentAddressOverflow(usize),
+    /// The segment with this index has an alignment that isn't a power of two
+    BadAlignment(usize),
+    /// The loadable segment with this index is both writable and executable
+    WritableAndExecutable(usize),
+    /// PT_GNU_STACK asks for an executable stack
+    ExecutableStack,
+    /// The kernel needs to be linked against shared libraries
+    SharedLibraries,
+    /// The dynamic section contains a kind of relocation table that isn't supported
+    UnsupportedDynamicTag(i64),
+    /// Relocation entries aren't the size of the 64 bit relocation struct
+    BadRelocationSize(u64),
+    /// The relocation table isn't inside the file data of a loadable segment
+    RelocationsOutOfBounds,
+    /// Only `R_X86_64_RELATIVE` relocations are supported
+    UnsupportedRelocation(u32),
+    /// The relocation with this index doesn't point inside a loadable segment
+    RelocationOutOfBounds(usize),
+    /// The kernel asks for a program interpreter
+    Interpreter,
+    MultipleTlsSegments,
+    NoLoadableSegments,
 }
 
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::TooSmall => write!(f, "file is too small to be an ELF file"),
+            ElfError::BadMagic(magic) => write!(f, "invalid ELF magic {:#x}", magic),
+            ElfError::UnsupportedClass(class) => {
+                write!(f, "unsupported ELF class {}, expected 64 bit", class)
+            }
+            ElfError::UnsupportedEndianness(data) => {
+                write!(
+                    f,
+                    "unsupported data encoding {}, expected little endian",
+                    data
+                )
+            }
+            ElfError::UnsupportedMachine(machine) => {
+                write!(f, "unsupported machine {:#x}, expected x86_64", machine)
+            }
+            ElfError::UnsupportedType(ty) => {
+                write!(f, "unsupported file type {}, expected an executable", ty)
+            }
+            ElfError::BadProgramHeaderSize(size) => {
+                write!(f, "program header size {} is too small", size)
+            }
+            ElfError::ProgramHeadersOutOfBounds => {
+                write!(f, "program header table is outside of the file")
+            }
+            ElfError::SegmentOutOfBounds(index) => {
+                write!(f, "segment {} is outside of the file", index)
+            }
+            ElfError::SegmentFileSizeTooLarge(index) => {
+                write!(f, "segment {} is larger in the file than in memory", index)
+            }
+            ElfError::SegmentAddressOverflow(index) => {
+                write!(
+                    f,
+                    "segment {} ends past the end of the address space",
+                    index
+                )
+            }
+            ElfError::BadAlignment(index) => {
+                write!(
+                    f,
+                    "segment {} has an alignment that isn't a power of two",
+                    index
+                )
+            }
+            ElfError::WritableAndExecutable(index) => {
+                write!(f, "segment {} is both writable and executable", index)
+            }
+            ElfError::ExecutableStack => write!(f, "an executable stack is not supported"),
+            ElfError::SharedLibraries => write!(f, "dynamically linked kernels are not supported"),
+            ElfError::UnsupportedDynamicTag(tag) => {
+                write!(f, "unsupported dynamic section entry {}", tag)
+            }
+            ElfError::BadRelocationSize(size) => {
+                write!(f, "relocation entry size {} is invalid", size)
+            }
+            ElfError::RelocationsOutOfBounds => {
+                write!(f, "relocation table is outside of the loaded data")
+            }
+            ElfError::UnsupportedRelocation(ty) => {
+                write!(f, "unsupported relocation type {}", ty)
+            }
+            ElfError::RelocationOutOfBounds(index) => {
+                write!(f, "relocation {} is outside of the loaded segments", index)
+            }
+            ElfError::Interpreter => write!(f, "kernels with an interpreter are not supported"),
+            ElfError::MultipleTlsSegments => write!(f, "there is more than one TLS segment"),
+            ElfError::NoLoadableSegments => write!(f, "there are no loadable segments"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 #[repr(packed)]
 pub struct ELFHeader {
     pub e_ident: ELFIdent,
@@ -20,6 +397,7 @@ pub struct ELFHeader {
     pub e_shstrndx: u16,
 }
 
+#[derive(Clone, Copy)]
 #[repr(packed)]
 pub struct ELFIdent {
     pub magic_num: u32,
@@ -29,3 +407,57 @@ pub struct ELFIdent {
     pub os_abi: u8,
     _pad: [u8; 8],
 }
+
+/// Describes one segment of the file, `Elf64_Phdr` in the spec
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    /// Combination of `PF_X`, `PF_W` and PF_R
+    pub p_flags: u32,
+    /// Where the segment's data starts in the file
+    pub p_offset: u64,
+    /// The virtual address the segment is loaded at
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    /// Size of the segment's data in the file
+    pub p_filesz: u64,
+    /// Size of the segment in memory, the rest after `p_filesz` is zeroed
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+impl ProgramHeader {
+    pub fn is_writable(&self) -> bool {
+        self.p_flags & PF_W != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.p_flags & PF_X != 0
+    }
+}
+
+/// An entry of the dynamic section, `Elf64_Dyn` in the spec
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Dyn {
+    d_tag: i64,
+    d_val: u64,
+}
+
+/// A relocation with an explicit addend, `Elf64_Rela` in the spec
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Rela {
+    /// Virtual address of the value to relocate
+    pub r_offset: u64,
+    /// Symbol index (high 32 bits) and relocation type (low 32 bits)
+    pub r_info: u64,
+    pub r_addend: i64,
+}
+
+impl Rela {
+    pub fn ty(&self) -> u32 {
+        self.r_info as u32
+    }
+}
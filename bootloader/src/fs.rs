@@ -0,0 +1,179 @@
+use alloc::vec::Vec;
+use boot_info::PhysicalRange;
+use core::mem::MaybeUninit;
+use core::slice;
+use log::{info, warn};
+use uefi::prelude::*;
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::media::file::{
+    Directory, File, FileAttribute, FileInfo, FileMode, FileType, RegularFile,
+};
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::table::boot::{AllocateType, MemoryType, SearchType};
+
+/// The volume all files are read from, `None` until `init` found one
+static mut BOOT_VOLUME: Option<Handle> = None;
+
+/// Picks the volume files are read from: the one the bootloader itself was loaded from,
+/// or if that can't be found, the first one that has a file at `kernel_path`
+pub fn init(bs: &BootServices, image: Handle, kernel_path: &str) {
+    let device = bs
+        .handle_protocol::<LoadedImage>(image)
+        .ok()
+        .map(|loaded_image| unsafe { &*loaded_image.unwrap().get() }.device())
+        .filter(|&device| bs.handle_protocol::<SimpleFileSystem>(device).is_ok());
+    match device {
+        Some(device) => unsafe { BOOT_VOLUME = Some(device) },
+        None => {
+            warn!("Can't find the volume the bootloader was loaded from");
+            if !find_volume_with(bs, kernel_path) {
+                warn!("No volume has {}, using the first one", kernel_path);
+            }
+        }
+    }
+}
+
+/// Switches to the first volume that has a file at `path`, returns false if none has one
+pub fn find_volume_with(bs: &BootServices, path: &str) -> bool {
+    for handle in file_system_handles(bs) {
+        if open_on(bs, Some(handle), path).is_ok() {
+            info!(
+                "Found {} on another volume, reading all files from it",
+                path
+            );
+            unsafe { BOOT_VOLUME = Some(handle) };
+            return true;
+        }
+    }
+    false
+}
+
+/// Every handle that supports the file system protocol
+fn file_system_handles(bs: &BootServices) -> Vec<Handle> {
+    let count = match bs.locate_handle(SearchType::from_proto::<SimpleFileSystem>(), None) {
+        Ok(count) => count.unwrap(),
+        Err(_) => return Vec::new(),
+    };
+    let mut handles = vec![MaybeUninit::uninit(); count];
+    match bs.locate_handle(
+        SearchType::from_proto::<SimpleFileSystem>(),
+        Some(&mut handles),
+    ) {
+        Ok(count) => handles[..count.unwrap()]
+            .iter()
+            .map(|handle| unsafe { handle.assume_init() })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Reads a whole file from the boot volume, `path` can use either kind of slash
+///
+/// Returns the firmware's status if the file can't be read, `NOT_FOUND` if it doesn't exist
+pub fn read_file(bs: &BootServices, path: &str) -> Result<Vec<u8>, Status> {
+    let (mut file, size) = open(bs, path)?;
+    let mut data = vec![0; size as usize];
+    file.read(&mut data).map_err(|err| err.status())?.unwrap();
+    Ok(data)
+}
+
+/// Reads a whole file from the boot volume into newly allocated pages of the given type,
+/// for files that have to stay in memory after exiting boot services
+pub fn read_file_to_pages(
+    bs: &BootServices,
+    path: &str,
+    memory_type: MemoryType,
+) -> Result<PhysicalRange, Status> {
+    let (mut file, size) = open(bs, path)?;
+    // An empty range doesn't need any memory, and nobody would free a page allocated for it
+    if size == 0 {
+        return Ok(PhysicalRange { start: 0, end: 0 });
+    }
+    let page_count = ((size + 0xFFF) / 0x1000) as usize;
+    let start = bs
+        .allocate_pages(AllocateType::AnyPages, memory_type, page_count)
+        .map_err(|err| err.status())?
+        .unwrap();
+    let data = unsafe { slice::from_raw_parts_mut(start as *mut u8, size as usize) };
+    if let Err(err) = file.read(data) {
+        bs.free_pages(start, page_count).unwrap().unwrap();
+        return Err(err.status());
+    }
+    Ok(PhysicalRange {
+        start,
+        end: start + size,
+    })
+}
+
+/// Writes a whole file on the boot volume, replacing it if it already exists
+pub fn write_file(bs: &BootServices, path: &str, data: &[u8]) -> Result<(), Status> {
+    let mut volume = open_volume(bs)?;
+    let path = path.replace('/', "\\");
+    // Writing to an existing file keeps whatever was past the end of the new contents
+    if let Ok(handle) = volume.open(&path, FileMode::ReadWrite, FileAttribute::empty()) {
+        handle
+            .unwrap()
+            .delete()
+            .map_err(|err| err.status())?
+            .unwrap();
+    }
+    let handle = volume
+        .open(&path, FileMode::CreateReadWrite, FileAttribute::empty())
+        .map_err(|err| err.status())?
+        .unwrap();
+    let mut file = match handle.into_type().map_err(|err| err.status())?.unwrap() {
+        FileType::Regular(file) => file,
+        FileType::Dir(_) => return Err(Status::INVALID_PARAMETER),
+    };
+    file.write(data).map_err(|err| err.status())?.unwrap();
+    file.flush().map_err(|err| err.status())?.unwrap();
+    Ok(())
+}
+
+/// Opens the root directory of the boot volume
+fn open_volume(bs: &BootServices) -> Result<Directory, Status> {
+    open_volume_on(bs, unsafe { BOOT_VOLUME })
+}
+
+/// Opens the root directory of the volume with the given handle,
+/// or of whichever one the firmware finds first without one
+fn open_volume_on(bs: &BootServices, handle: Option<Handle>) -> Result<Directory, Status> {
+    let fs = match handle {
+        Some(handle) => bs.handle_protocol::<SimpleFileSystem>(handle),
+        None => bs.locate_protocol::<SimpleFileSystem>(),
+    };
+    let fs = fs.map_err(|err| err.status())?.unwrap();
+    let fs = unsafe { fs.get().as_mut().unwrap() };
+    Ok(fs.open_volume().map_err(|err| err.status())?.unwrap())
+}
+
+/// Opens a regular file on the boot volume and returns it along with its size
+fn open(bs: &BootServices, path: &str) -> Result<(RegularFile, u64), Status> {
+    open_on(bs, unsafe { BOOT_VOLUME }, path)
+}
+
+/// Opens a regular file on the volume with the given handle, see `open_volume_on`
+fn open_on(
+    bs: &BootServices,
+    volume: Option<Handle>,
+    path: &str,
+) -> Result<(RegularFile, u64), Status> {
+    let mut volume = open_volume_on(bs, volume)?;
+    let path = path.replace('/', "\\");
+    let handle = volume
+        .open(&path, FileMode::Read, FileAttribute::READ_ONLY)
+        .map_err(|err| err.status())?
+        .unwrap();
+    let mut file = match handle.into_type().map_err(|err| err.status())?.unwrap() {
+        FileType::Regular(file) => file,
+        FileType::Dir(_) => return Err(Status::INVALID_PARAMETER),
+    };
+    // The info contains the file name, so leave plenty of space for it
+    let mut info_buf = vec![0; 512];
+    let size = file
+        .get_info::<FileInfo>(&mut info_buf)
+        .map_err(|err| err.status())?
+        .unwrap()
+        .file_size();
+    Ok((file, size))
+}
@@ -0,0 +1,174 @@
+use alloc::vec::Vec;
+use boot_info::{PixelFormat as FramebufferFormat, PixelMasks};
+use core::ptr;
+use log::{info, warn};
+use uefi::prelude::*;
+use uefi::proto::console::gop::{GraphicsOutput, Mode, ModeInfo, PixelFormat};
+use uefi::proto::Protocol;
+use uefi::unsafe_guid;
+
+use crate::config::{BootConfig, ResolutionFallback};
+
+/// EFI_EDID_ACTIVE_PROTOCOL, the EDID the monitor reported, which uefi-rs doesn't have yet
+#[repr(C)]
+#[unsafe_guid("bd8c1056-9f36-44ec-92a8-a6337f817986")]
+#[derive(Protocol)]
+struct EdidActive {
+    size: u32,
+    edid: *const u8,
+}
+
+/// Picks a graphics mode based on the config and switches to it
+///
+/// Only modes with a linear framebuffer in a format the kernel can draw in are considered.
+/// Returns `None` if there is no GOP or none of its modes qualify,
+/// the kernel then has to do without a framebuffer
+pub fn set_gop_mode<'a>(
+    bs: &'a BootServices,
+    config: &BootConfig,
+) -> Option<(&'a mut GraphicsOutput<'a>, Mode)> {
+    let gop = match bs.locate_protocol::<GraphicsOutput>() {
+        Ok(gop) => gop.unwrap(),
+        Err(err) => {
+            warn!("No graphics output protocol: {:?}", err.status());
+            return None;
+        }
+    };
+    let gop = unsafe { &mut *gop.get() };
+    let mut modes: Vec<Mode> = gop
+        .modes()
+        .map(|mode| mode.unwrap())
+        .filter(|mode| framebuffer_format(mode.info()).is_some())
+        .collect();
+    if modes.is_empty() {
+        warn!("No graphics mode has a linear framebuffer in a supported pixel format");
+        return None;
+    }
+
+    let preferred = config.resolution.and_then(|resolution| {
+        modes
+            .iter()
+            .find(|mode| mode.info().resolution() == resolution)
+    });
+    let selected = match preferred {
+        Some(mode) => mode,
+        None => {
+            if let Some((width, height)) = config.resolution {
+                warn!("Resolution {}x{} is not available", width, height);
+            }
+            match (config.resolution_fallback, config.resolution) {
+                (ResolutionFallback::Closest, Some(resolution)) => closest(&modes, resolution),
+                (ResolutionFallback::Native, _) => match native_resolution(bs) {
+                    Some(resolution) => closest(&modes, resolution),
+                    None => {
+                        warn!("The monitor's resolution is unknown, using the largest mode");
+                        largest(&modes)
+                    }
+                },
+                _ => largest(&modes),
+            }
+        }
+    };
+    let index = modes
+        .iter()
+        .position(|mode| ptr::eq(mode, selected))
+        .unwrap();
+    let mode = modes.swap_remove(index);
+
+    gop.set_mode(&mode)
+        .expect_success("Failed to set the graphics mode");
+    let (width, height) = mode.info().resolution();
+    info!(
+        "Set GOP mode to {}x{} ({:?})",
+        width,
+        height,
+        mode.info().pixel_format()
+    );
+    Some((gop, mode))
+}
+
+/// The pixel format and color masks of a mode, as the kernel sees them
+/// Returns `None` for modes without a linear framebuffer
+pub fn framebuffer_format(info: &ModeInfo) -> Option<(FramebufferFormat, PixelMasks)> {
+    match info.pixel_format() {
+        PixelFormat::Rgb => Some((
+            FramebufferFormat::Rgb,
+            PixelMasks {
+                red: 0x0000_00FF,
+                green: 0x0000_FF00,
+                blue: 0x00FF_0000,
+            },
+        )),
+        PixelFormat::Bgr => Some((
+            FramebufferFormat::Bgr,
+            PixelMasks {
+                red: 0x00FF_0000,
+                green: 0x0000_FF00,
+                blue: 0x0000_00FF,
+            },
+        )),
+        PixelFormat::Bitmask => {
+            let mask = info.pixel_bitmask()?;
+            Some((
+                FramebufferFormat::Bitmask,
+                PixelMasks {
+                    red: mask.red,
+                    green: mask.green,
+                    blue: mask.blue,
+                },
+            ))
+        }
+        PixelFormat::BltOnly => None,
+    }
+}
+
+fn largest(modes: &[Mode]) -> &Mode {
+    modes
+        .iter()
+        .max_by_key(|mode| {
+            let (width, height) = mode.info().resolution();
+            width * height
+        })
+        .unwrap()
+}
+
+/// The mode whose width and height differ the least from `resolution`
+fn closest(modes: &[Mode], resolution: (usize, usize)) -> &Mode {
+    modes
+        .iter()
+        .min_by_key(|mode| {
+            let (width, height) = mode.info().resolution();
+            abs_diff(width, resolution.0) + abs_diff(height, resolution.1)
+        })
+        .unwrap()
+}
+
+fn abs_diff(a: usize, b: usize) -> usize {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+/// Reads the preferred resolution from the first detailed timing descriptor of the EDID
+fn native_resolution(bs: &BootServices) -> Option<(usize, usize)> {
+    let edid = bs.locate_protocol::<EdidActive>().ok()?.unwrap();
+    let edid = unsafe { &*edid.get() };
+    if edid.size < 128 || edid.edid.is_null() {
+        return None;
+    }
+    let edid = unsafe { core::slice::from_raw_parts(edid.edid, edid.size as usize) };
+    if edid[0..8] != [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00] {
+        return None;
+    }
+    // A pixel clock of 0 means the descriptor isn't a timing
+    let timing = &edid[54..72];
+    if timing[0] == 0 && timing[1] == 0 {
+        return None;
+    }
+    let width = timing[2] as usize | (timing[4] as usize & 0xF0) << 4;
+    let height = timing[5] as usize | (timing[7] as usize & 0xF0) << 4;
+    info!("The monitor's native resolution is {}x{}", width, height);
+    Some((width, height))
+}
@@ -0,0 +1,72 @@
+use core::arch::x86_64::_rdtsc;
+use core::ptr;
+use log::info;
+use uefi::prelude::*;
+use uefi::proto::Protocol;
+use uefi::{unsafe_guid, Guid};
+
+/// Lowest address a position independent kernel is loaded at, the start of the top 2GiB
+const KERNEL_BASE_MIN: u64 = 0xffff_ffff_8000_0000;
+/// The kernel is placed somewhere inside of this many bytes starting at `KERNEL_BASE_MIN`
+const KERNEL_BASE_RANGE: u64 = 0x4000_0000;
+
+/// EFI_RNG_PROTOCOL, which uefi-rs doesn't have yet
+#[repr(C)]
+#[unsafe_guid("3152bca5-eade-433d-862e-c01cdc291f44")]
+#[derive(Protocol)]
+struct Rng {
+    _get_info: extern "efiapi" fn(this: &mut Rng, list_size: &mut usize, list: *mut Guid) -> Status,
+    get_rng: extern "efiapi" fn(
+        this: &mut Rng,
+        algorithm: *const Guid,
+        value_length: usize,
+        value: *mut u8,
+    ) -> Status,
+}
+
+/// Picks a random address aligned to `align` where `size` bytes of kernel fit
+pub fn choose_kernel_base(bs: &BootServices, size: u64, align: u64) -> u64 {
+    assert!(
+        size <= KERNEL_BASE_RANGE,
+        "The kernel is too large to be loaded ({} bytes)",
+        size
+    );
+    let slots = (KERNEL_BASE_RANGE - size) / align + 1;
+    KERNEL_BASE_MIN + random_u64(bs) % slots * align
+}
+
+fn random_u64(bs: &BootServices) -> u64 {
+    match firmware_random(bs) {
+        Some(value) => {
+            info!("Randomizing the kernel base with the firmware's RNG");
+            value
+        }
+        None => {
+            info!("No RNG protocol, randomizing the kernel base with the TSC");
+            tsc_random()
+        }
+    }
+}
+
+/// Asks the RNG protocol for 8 random bytes using its default algorithm
+fn firmware_random(bs: &BootServices) -> Option<u64> {
+    let rng = bs.locate_protocol::<Rng>().ok()?.unwrap();
+    let rng = unsafe { &mut *rng.get() };
+    let mut value = 0u64;
+    let status = (rng.get_rng)(rng, ptr::null(), 8, &mut value as *mut u64 as *mut u8);
+    if status == Status::SUCCESS {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Only the low bits of the TSC are hard to guess, so they are spread over the whole
+/// value with the splitmix64 finalizer. This is not a good source of entropy, but how long
+/// the firmware took to get here still varies enough between boots
+fn tsc_random() -> u64 {
+    let mut x = unsafe { _rdtsc() };
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,199 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use log::{LevelFilter, Log, Metadata, Record};
+use uefi::prelude::*;
+use uefi::proto::console::serial::Serial;
+use uefi::proto::console::text::Output;
+use uefi::proto::Protocol;
+use uefi::table::boot::SearchType;
+use uefi::unsafe_guid;
+use x86_64::instructions::port::Port;
+
+use crate::fs;
+
+/// Path of the copy of the log on the boot volume
+const LOG_PATH: &str = "bootlog.txt";
+
+/// I/O port of the first serial port (COM1), which the firmware has already set up
+const COM1: u16 = 0x3F8;
+
+// Device path node types and the subtype of UART nodes
+const MESSAGING_DEVICE_PATH: u8 = 3;
+const UART_DEVICE_PATH: u8 = 14;
+const END_DEVICE_PATH: u8 = 0x7F;
+
+static LOGGER: BootLogger = BootLogger {
+    state: UnsafeCell::new(None),
+    finished: AtomicBool::new(false),
+};
+
+/// Writes every message to the UEFI console, to the serial port if the firmware has one,
+/// and to a buffer that ends up in `bootlog.txt` and is handed to the kernel
+///
+/// After `finish` messages only go straight to COM1, so that a panic while exiting boot
+/// services or afterwards still shows up somewhere
+struct BootLogger {
+    /// `None` before `init` and after `finish`
+    state: UnsafeCell<Option<LoggerState>>,
+    finished: AtomicBool,
+}
+
+struct LoggerState {
+    console: *mut Output<'static>,
+    serial: Option<*mut Serial<'static>>,
+    buffer: String,
+}
+
+// The bootloader runs on a single processor, and nothing logs from interrupts
+unsafe impl Sync for BootLogger {}
+unsafe impl Send for BootLogger {}
+
+/// Installs the logger, the allocator has to be set up before since the log is kept in memory
+pub fn init(st: &SystemTable<Boot>) {
+    let bs = st.boot_services();
+    // Firmware like OVMF already shows the console on the serial port,
+    // writing to it as well would show every line twice
+    let serial = if console_on_serial(bs) {
+        None
+    } else {
+        bs.locate_protocol::<Serial>()
+            .ok()
+            .map(|serial| serial.unwrap().get() as *mut Serial<'static>)
+    };
+    let state = LoggerState {
+        console: st.stdout() as *mut Output as *mut Output<'static>,
+        serial,
+        buffer: String::new(),
+    };
+    unsafe { *LOGGER.state.get() = Some(state) };
+    log::set_logger(&LOGGER).expect("A logger was already set");
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Writes the log to `bootlog.txt` and stops logging, returns the log so it can be passed
+/// to the kernel. Has to be called before exiting boot services, after it messages only go to COM1
+pub fn finish(bs: &BootServices) -> &'static str {
+    if let Err(status) = fs::write_file(bs, LOG_PATH, LOGGER.buffer().as_bytes()) {
+        log::warn!("Can't write {}: {:?}", LOG_PATH, status);
+    }
+    let buffer = unsafe { (*LOGGER.state.get()).take() }
+        .map(|state| state.buffer)
+        .unwrap_or_default();
+    LOGGER.finished.store(true, Ordering::Relaxed);
+    Box::leak(buffer.into_boxed_str())
+}
+
+impl BootLogger {
+    fn buffer(&self) -> &str {
+        match unsafe { &*self.state.get() } {
+            Some(state) => &state.buffer,
+            None => "",
+        }
+    }
+}
+
+impl Log for BootLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let state = match unsafe { &mut *self.state.get() } {
+            Some(state) => state,
+            None => {
+                if self.finished.load(Ordering::Relaxed) {
+                    let _ = writeln!(RawSerial, "[{:>5}] {}", record.level(), record.args());
+                }
+                return;
+            }
+        };
+        let start = state.buffer.len();
+        let _ = writeln!(state.buffer, "[{:>5}] {}", record.level(), record.args());
+        let line = &state.buffer[start..];
+
+        let console = unsafe { &mut *state.console };
+        let _ = console.write_str(line);
+        if let Some(serial) = state.serial {
+            let serial = unsafe { &mut *serial };
+            // Terminals expect a carriage return before every newline
+            for (i, part) in line.split('\n').enumerate() {
+                if i > 0 {
+                    let _ = serial.write(b"\r\n");
+                }
+                let _ = serial.write(part.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Writes to COM1 through its I/O ports, which works without boot services and allocations
+struct RawSerial;
+
+impl Write for RawSerial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut data = Port::<u8>::new(COM1);
+        let mut line_status = Port::<u8>::new(COM1 + 5);
+        for byte in s.bytes() {
+            // Terminals expect a carriage return before every newline
+            if byte == b'\n' {
+                RawSerial.write_str("\r")?;
+            }
+            unsafe {
+                while line_status.read() & 0x20 == 0 {}
+                data.write(byte);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The header of a node of EFI_DEVICE_PATH_PROTOCOL, which is a list of them
+/// The data of every node follows its header, `length` includes both
+#[repr(C)]
+#[unsafe_guid("09576e91-6d3f-11d2-8e39-00a0c969723b")]
+#[derive(Protocol)]
+struct DevicePathNode {
+    ty: u8,
+    sub_type: u8,
+    length: [u8; 2],
+}
+
+/// Whether one of the firmware's text consoles is a serial port, by looking for a UART
+/// in the device paths of all the text outputs
+fn console_on_serial(bs: &BootServices) -> bool {
+    let search = || SearchType::from_proto::<Output>();
+    let count = match bs.locate_handle(search(), None) {
+        Ok(count) => count.unwrap(),
+        Err(_) => return false,
+    };
+    let mut handles = vec![MaybeUninit::uninit(); count];
+    let count = match bs.locate_handle(search(), Some(&mut handles)) {
+        Ok(count) => count.unwrap(),
+        Err(_) => return false,
+    };
+    handles[..count].iter().any(|handle| {
+        let path = match bs.handle_protocol::<DevicePathNode>(unsafe { handle.assume_init() }) {
+            Ok(path) => path.unwrap().get() as *const u8,
+            // The console splitter that combines all outputs has no device path
+            Err(_) => return false,
+        };
+        let mut node = path;
+        loop {
+            let (ty, sub_type) = unsafe { (*node, *node.add(1)) };
+            let length = unsafe { u16::from_le_bytes([*node.add(2), *node.add(3)]) };
+            if ty == MESSAGING_DEVICE_PATH && sub_type == UART_DEVICE_PATH {
+                return true;
+            }
+            if ty == END_DEVICE_PATH || length < 4 {
+                return false;
+            }
+            node = unsafe { node.add(length as usize) };
+        }
+    })
+}
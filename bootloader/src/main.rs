@@ -1,165 +1,577 @@
 #![no_std]
 #![no_main]
 #![feature(abi_efiapi)]
+#![feature(asm)]
 #[macro_use]
 extern crate alloc;
+// Only linked for its panic handler, it would install a logger of its own when initialized
+extern crate uefi_services;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::arch::x86_64::_rdtsc;
 use core::{mem, ptr, u8};
 
+mod config;
+mod decompress;
 mod elf;
-use elf::read_elf_header;
-use log::info;
-use uefi::proto::console::gop::{GraphicsOutput, Mode, PixelFormat};
-use uefi::proto::media::file::FileInfo;
-use uefi::proto::media::file::{File, FileAttribute, FileType::Regular};
-use uefi::proto::media::fs::SimpleFileSystem;
-use uefi::table::boot::MemoryDescriptor;
+mod fs;
+mod graphics;
+mod kaslr;
+mod logger;
+mod memory;
+mod menu;
+mod paging;
+mod sha256;
+mod tftp;
+use boot_info::{
+    BootInfo, BootTimestamps, ByteSlice, FirmwareTables, FramebufferInfo, MemoryMap, MemoryRegion,
+    PhysicalRange, TlsTemplate, VirtualRange,
+};
+use config::{BootConfig, BootEntry, FileSource};
+use decompress::DecompressError;
+use elf::ElfFile;
+use log::{error, info, warn};
+use memory::{INITRD_MEMORY_TYPE, KERNEL_MEMORY_TYPE};
+use paging::{KernelPageTables, PHYSICAL_MEMORY_OFFSET};
+use sha256::Digest;
+use tftp::Tftp;
+use uefi::proto::console::gop::{GraphicsOutput, Mode};
+use uefi::table::boot::{AllocateType, MemoryDescriptor};
+use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID, SMBIOS3_GUID};
 use uefi::{data_types::*, prelude::*};
+use x86_64::structures::paging::PageTableFlags;
 
-#[repr(C)]
-struct GopRes {
-    x: u64,
-    y: u64,
-}
-#[repr(C)]
-pub struct GopInfo {
-    pointer: *mut u8,
-    size: u64,
-    resolution: GopRes,
-    stride: u64,
-}
+/// How often exiting boot services is tried before giving up
+const EXIT_ATTEMPTS: usize = 8;
 
 #[entry]
-fn efi_main(image: Handle, mut st: SystemTable<Boot>) -> Status {
-    uefi_services::init(&mut st).expect_success("Failed to initialize utilities");
+fn efi_main(image: Handle, st: SystemTable<Boot>) -> Status {
+    let mut timestamps = BootTimestamps::empty();
+    timestamps.bootloader_entry = unsafe { _rdtsc() };
+    unsafe { uefi::alloc::init(st.boot_services()) };
     st.stdout()
         .reset(false)
         .expect_success("Failed to reset stdout");
+    logger::init(&st);
     let bs = st.boot_services();
+    fs::init(bs, image, &BootEntry::default().kernel_path);
 
-    let (gop, gop_mode) = set_gop_mode(bs);
-    let kernel = load_kernel(bs);
+    let config = BootConfig::load(bs);
+    log::set_max_level(config.verbosity);
+    let entry = menu::select_entry(&st, &config);
+    info!("Booting {}", entry.title);
+    let graphics_mode = graphics::set_gop_mode(bs, &config);
+    timestamps.gop_set = unsafe { _rdtsc() };
+    let mut tftp = match config.source {
+        FileSource::Tftp => match Tftp::connect(bs, image, config.tftp_server) {
+            Ok(tftp) => Some(tftp),
+            Err(status) => {
+                warn!("Can't use TFTP: {:?}, loading from the ESP instead", status);
+                None
+            }
+        },
+        FileSource::Esp => None,
+    };
+    let (kernel_path, kernel) = read_kernel(bs, &mut tftp, &entry.kernel_path);
+    timestamps.kernel_read = unsafe { _rdtsc() };
+    // The file is checked as it was read, so a compressed kernel that was damaged
+    // is reported as such instead of failing to decompress
+    if !verify_file(bs, &mut tftp, &kernel_path, &kernel, entry.kernel_sha256) {
+        return Status::SECURITY_VIOLATION;
+    }
+    let kernel = match decompress_kernel(kernel) {
+        Ok(kernel) => kernel,
+        Err(err) => {
+            error!("Failed to decompress kernel {}: {}", kernel_path, err);
+            return Status::LOAD_ERROR;
+        }
+    };
+    let initrd = load_initrd(bs, &mut tftp, &entry.initrd_path);
+    if !initrd.is_empty() {
+        // Boot services memory is identity mapped
+        let data = unsafe {
+            core::slice::from_raw_parts(initrd.start as *const u8, initrd.len() as usize)
+        };
+        if !verify_file(bs, &mut tftp, &entry.initrd_path, data, entry.initrd_sha256) {
+            return Status::SECURITY_VIOLATION;
+        }
+    }
+    let kernel = match ElfFile::parse(&kernel) {
+        Ok(kernel) => kernel,
+        Err(err) => {
+            error!("Invalid kernel file: {}", err);
+            return Status::LOAD_ERROR;
+        }
+    };
 
     unsafe {
+        let mut page_tables = KernelPageTables::new(bs);
         info!("Copying Kernel...");
-        let kernel_entry = copy_kernel_segments(kernel);
-
-        let gop_info = GopInfo {
-            pointer: gop.frame_buffer().as_mut_ptr(),
-            size: (gop.frame_buffer().size() / 4) as u64,
-            resolution: GopRes {
-                x: gop_mode.info().resolution().0 as u64,
-                y: gop_mode.info().resolution().1 as u64,
-            },
-            stride: gop_mode.info().stride() as u64,
+        let loaded = copy_kernel_segments(bs, &kernel, &mut page_tables);
+        timestamps.segments_copied = _rdtsc();
+        let stack = allocate_kernel_stack(bs, &mut page_tables, config.stack_size);
+        page_tables.map_physical_memory(memory::max_physical_address(bs));
+        page_tables.map_trampoline();
+        let framebuffer = match graphics_mode {
+            Some((gop, mode)) => map_framebuffer(&mut page_tables, gop, &mode),
+            None => {
+                warn!("There is no framebuffer, the kernel only has the serial port");
+                FramebufferInfo::empty()
+            }
         };
+        let page_tables = page_tables.pml4_address();
+
+        // Allocated from loader data, which stays untouched after exiting boot services
+        let boot_info = Box::leak(Box::new(BootInfo::new()));
+        boot_info.framebuffer = framebuffer;
+        boot_info.kernel_image = loaded.image;
+        boot_info.kernel_slide = loaded.slide;
+        if let Some(tls) = kernel.tls_segment() {
+            boot_info.tls_template = TlsTemplate {
+                start: tls.p_vaddr.wrapping_add(loaded.slide),
+                file_size: tls.p_filesz,
+                mem_size: tls.p_memsz,
+                align: tls.p_align,
+            };
+        }
+        boot_info.physical_memory_offset = PHYSICAL_MEMORY_OFFSET;
+        boot_info.initrd = initrd;
+        let cmdline = Box::leak(entry.cmdline.into_bytes().into_boxed_slice());
+        boot_info.cmdline = ByteSlice::from_raw_parts(
+            cmdline.as_ptr() as u64 + PHYSICAL_MEMORY_OFFSET,
+            cmdline.len() as u64,
+        );
 
+        boot_info.firmware_tables = firmware_tables(&st);
+        boot_info.kernel_stack = stack;
+        boot_info.timestamps = timestamps;
+
+        info!("Launching Kernel at {:X}", loaded.entry);
         info!("Exiting boot services...");
-        let max_mmap_size = bs.memory_map_size() + 8 * mem::size_of::<MemoryDescriptor>();
-        let mut mmap_buf = vec![0; max_mmap_size].into_boxed_slice();
-        st.exit_boot_services(image, &mut mmap_buf)
-            .expect_success("Failed to exit boot services");
-
-        info!("Launching Kernel at {:X}", kernel_entry);
-        let entry_fn: extern "sysv64" fn(GopInfo) -> ! = mem::transmute(kernel_entry);
-        entry_fn(gop_info);
+        let log = logger::finish(bs);
+        boot_info.bootloader_log = ByteSlice::from_raw_parts(
+            log.as_ptr() as u64 + PHYSICAL_MEMORY_OFFSET,
+            log.len() as u64,
+        );
+        // Nothing can be allocated after exiting boot services, so the space for the kernel's
+        // copy of the memory map has to be reserved now, one region for every possible descriptor.
+        // Both buffers have room for a few more descriptors, since allocating them can add some
+        let mmap_size = || bs.memory_map_size() + 8 * mem::size_of::<MemoryDescriptor>();
+        let mut mmap_buf = vec![0; mmap_size()].into_boxed_slice();
+        let mut regions = memory_regions_for(mmap_buf.len());
+        // Exiting fails with INVALID_PARAMETER if the memory map changed since it was fetched,
+        // which firmware is allowed to do at any time. Every attempt fetches the map again, and
+        // nothing in this loop may log since that could change it once more. If the map grew
+        // too large for the buffer, both buffers are allocated again before trying once more
+        let mut attempts = 0;
+        let (st, mmap) = loop {
+            attempts += 1;
+            match st.unsafe_clone().exit_boot_services(image, &mut mmap_buf) {
+                Ok(completion) => break completion.unwrap(),
+                Err(err)
+                    if err.status() == Status::INVALID_PARAMETER && attempts < EXIT_ATTEMPTS => {}
+                Err(err)
+                    if err.status() == Status::BUFFER_TOO_SMALL && attempts < EXIT_ATTEMPTS =>
+                {
+                    mmap_buf = vec![0; mmap_size()].into_boxed_slice();
+                    regions = memory_regions_for(mmap_buf.len());
+                }
+                // Boot services are still there, so the firmware can go on to other boot options.
+                // The log is finished by now, so this only goes to the serial port
+                Err(err) => {
+                    error!(
+                        "Failed to exit boot services after {} attempts: {:?}",
+                        attempts,
+                        err.status()
+                    );
+                    return err.status();
+                }
+            }
+        };
+        let regions = Box::leak(regions);
+        boot_info.timestamps.boot_services_exited = _rdtsc();
+        uefi::alloc::exit_boot_services();
+        // The runtime system table is just a pointer to the firmware's table
+        boot_info.firmware_tables.system_table = mem::transmute::<_, usize>(st) as u64;
+
+        let region_count = memory::convert_memory_map(mmap, regions);
+        // The kernel accesses everything the bootloader allocated through the physical memory mapping
+        boot_info.memory_map = MemoryMap::from_raw_parts(
+            regions.as_ptr() as u64 + PHYSICAL_MEMORY_OFFSET,
+            region_count as u64,
+        );
+        let boot_info = boot_info as *const BootInfo as u64 + PHYSICAL_MEMORY_OFFSET;
+
+        paging::enter_kernel(page_tables, loaded.entry, boot_info, stack.end);
     }
 }
 
-/// Sets the graphics output mode to 1600x900, with bgr pixel format
-fn set_gop_mode(bs: &BootServices) -> (&mut GraphicsOutput, Mode) {
-    let gop_raw = bs.locate_protocol::<GraphicsOutput>().unwrap().unwrap();
-    let gop = unsafe { gop_raw.get().as_mut().unwrap() };
-    let gop_2 = unsafe { gop_raw.get().as_mut().unwrap() };
-    let modes = gop.modes();
-    let mut gop_mode: Option<Mode> = None;
-    for mode in modes {
-        let mode = mode.unwrap();
-        let info = mode.info();
-        let res = info.resolution();
-        if info.pixel_format() == PixelFormat::Bgr && res.0 == 1600 && res.1 == 900 {
-            gop_2.set_mode(&mode).unwrap().unwrap();
-            gop_mode = Some(mode);
-            info!("Set GOP mode");
-            break;
+/// Space for the kernel's copy of a memory map that fits into `mmap_size` bytes
+fn memory_regions_for(mmap_size: usize) -> Box<[MemoryRegion]> {
+    vec![MemoryRegion::empty(); mmap_size / mem::size_of::<MemoryDescriptor>()].into_boxed_slice()
+}
+
+/// Reads the kernel file and returns its path along with its contents, which may be compressed
+///
+/// If there is no file at `path`, `<path>.gz` and `<path>.lz4` are tried as well,
+/// and if the boot volume has none of them the other volumes are searched.
+/// A kernel that can't be fetched over TFTP is loaded from the ESP, and then the digest
+/// and initrd come from there as well
+fn read_kernel(bs: &BootServices, tftp: &mut Option<Tftp>, path: &str) -> (String, Vec<u8>) {
+    info!("Loading {}", path);
+    let paths = [
+        path.to_string(),
+        format!("{}.gz", path),
+        format!("{}.lz4", path),
+    ];
+    let fetched = tftp.as_mut().and_then(|tftp| {
+        read_first_file(&paths, |path| tftp.read_file(path))
+            .map_err(|status| {
+                warn!(
+                    "Can't fetch {} over TFTP: {:?}, loading it from the ESP",
+                    path, status
+                )
+            })
+            .ok()
+    });
+    match fetched {
+        Some(file) => file,
+        None => {
+            *tftp = None;
+            let mut result = read_first_file(&paths, |path| fs::read_file(bs, path));
+            if matches!(result, Err(Status::NOT_FOUND))
+                && paths.iter().any(|path| fs::find_volume_with(bs, path))
+            {
+                result = read_first_file(&paths, |path| fs::read_file(bs, path));
+            }
+            result.unwrap_or_else(|status| panic!("Failed to load kernel {}: {:?}", path, status))
         }
     }
-    (gop, gop_mode.expect("Resolution not available"))
 }
 
-/// Loads kernel.elf from filesystem into vector of bytes and returns it
-fn load_kernel(bs: &BootServices) -> alloc::vec::Vec<u8> {
-    let fs = bs.locate_protocol::<SimpleFileSystem>().unwrap().unwrap();
-    let fs = unsafe { fs.get().as_mut().unwrap() };
-    let mut volume = fs.open_volume().unwrap().unwrap();
-    let kernel_handle = volume
-        .open(
-            "kernel.elf",
-            uefi::proto::media::file::FileMode::Read,
-            FileAttribute::READ_ONLY,
-        )
-        .expect("Failed to load kernel")
-        .unwrap();
-    let kernel = if let Regular(mut kernel_file) = kernel_handle.into_type().unwrap().unwrap() {
-        let mut info_buf = vec![0; 102];
-        let kernel_size = kernel_file
-            .get_info::<FileInfo>(&mut info_buf)
-            .unwrap()
-            .unwrap()
-            .file_size();
-        let mut out_buffer = vec![0; kernel_size as usize];
-        kernel_file.read(&mut out_buffer).unwrap().unwrap();
-        out_buffer
-    } else {
-        panic!("Kernel file is a directory");
-    };
-    kernel
+/// Kernels compressed with gzip or LZ4 are recognized by their magic number and decompressed,
+/// anything else is returned as is
+fn decompress_kernel(data: Vec<u8>) -> Result<Vec<u8>, DecompressError> {
+    match decompress::compression(&data) {
+        Some(compression) => {
+            info!(
+                "Decompressing the kernel ({:?}, {} bytes)",
+                compression,
+                data.len()
+            );
+            let kernel = decompress::decompress(compression, &data)?;
+            info!("Decompressed the kernel to {} bytes", kernel.len());
+            Ok(kernel)
+        }
+        None => Ok(data),
+    }
 }
 
-// TODO: make sure that the kernel is written into unused memory,
-// however the only memory used should be for the kernel byte array which isn't needed
-// after this, so it _should_ be okay, maybe
-/// Copies all the program segments in the kernel elf file to their specified memory location
-/// and returns the kernel's entry point address
-unsafe fn copy_kernel_segments(kernel: alloc::vec::Vec<u8>) -> u64 {
-    // Get info about the program segments from the header
-    let header = read_elf_header(&kernel);
-    let kernel_entry = header.e_entry as u64;
-    let program_headers_offset = header.e_phoff;
-    let entry_size = header.e_phentsize;
-    let entry_count = header.e_phnum;
-
-    let program_headers_ptr = kernel.as_ptr().add(program_headers_offset as usize);
+/// Reads the first of the files that exists with `read`, returns its path and contents
+fn read_first_file(
+    paths: &[String],
+    mut read: impl FnMut(&str) -> Result<Vec<u8>, Status>,
+) -> Result<(String, Vec<u8>), Status> {
+    for (i, path) in paths.iter().enumerate() {
+        match read(path) {
+            Err(Status::NOT_FOUND) => continue,
+            Ok(data) => {
+                if i > 0 {
+                    info!("Loading {} instead", path);
+                }
+                return Ok((path.clone(), data));
+            }
+            Err(status) => return Err(status),
+        }
+    }
+    Err(Status::NOT_FOUND)
+}
 
-    // Load each entry
-    for i in 0..entry_count {
-        // Get a pointer to the beginning of the header
-        let header_ptr = program_headers_ptr.add((i * entry_size).into());
-        // If the segment type is 1, it should be loaded
-        // Other segments can be ignored I think
-        let segment_type = *(header_ptr as *const u32);
-        if segment_type == 1 {
-            // The offset in the file where the actual code is located
-            let data_offset = *(header_ptr.offset(0x8) as *const u64);
-            // The memory address where the code should be loaded into
-            let mem_addr = *(header_ptr.offset(0x10) as *const u64);
-            // The size of the segment in the file
-            let size_file = *(header_ptr.offset(0x20) as *const u64);
-            // The amount of memory that should be allocated for the segment
-            let size_mem = *(header_ptr.offset(0x28) as *const u64);
+/// Loads the initial ramdisk into its own pages and returns where it is
+/// The range is empty if there is no initrd, or if it can't be read since the kernel
+/// can boot without one
+///
+/// An initrd that can't be fetched over TFTP is loaded from the ESP, and then its digest
+/// comes from there as well
+fn load_initrd(bs: &BootServices, tftp: &mut Option<Tftp>, path: &str) -> PhysicalRange {
+    let fetched = tftp.as_mut().and_then(|tftp| {
+        tftp.read_file_to_pages(bs, path, INITRD_MEMORY_TYPE)
+            .map_err(|status| {
+                warn!(
+                    "Can't fetch {} over TFTP: {:?}, loading it from the ESP",
+                    path, status
+                )
+            })
+            .ok()
+    });
+    let result = match fetched {
+        Some(initrd) => Ok(initrd),
+        None => {
+            *tftp = None;
+            fs::read_file_to_pages(bs, path, INITRD_MEMORY_TYPE)
+        }
+    };
+    match result {
+        Ok(initrd) => {
             info!(
-                "Writing segment of size {} from {:X} to {:X}",
-                size_mem, data_offset, mem_addr
+                "Loaded {} ({} bytes) at {:X}",
+                path,
+                initrd.len(),
+                initrd.start
             );
-            // Clear out space for the segment
-            ptr::write_bytes(mem_addr as *mut u8, 0, size_mem as usize);
-            // Copy the segment from the file buffer to the memory address
-            ptr::copy(
-                kernel.as_ptr().add(data_offset as usize),
-                mem_addr as *mut u8,
-                size_file as usize,
+            initrd
+        }
+        Err(Status::NOT_FOUND) => {
+            info!("No initrd at {}", path);
+            PhysicalRange { start: 0, end: 0 }
+        }
+        Err(status) => {
+            warn!(
+                "Failed to load initrd {}: {:?}, booting without it",
+                path, status
             );
+            PhysicalRange { start: 0, end: 0 }
+        }
+    }
+}
+
+/// Checks a loaded file against its expected SHA-256 digest, returns false if it doesn't match
+///
+/// The digest from `boot.cfg` takes precedence over a detached `<path>.sha256` file in the
+/// format `sha256sum` writes, which comes from the same place as the file.
+/// Files without either are booted unverified
+fn verify_file(
+    bs: &BootServices,
+    tftp: &mut Option<Tftp>,
+    path: &str,
+    data: &[u8],
+    expected: Option<Digest>,
+) -> bool {
+    let expected = match expected {
+        Some(digest) => digest,
+        None => {
+            let digest_path = format!("{}.sha256", path);
+            let contents = match tftp {
+                Some(tftp) => tftp.read_file(&digest_path),
+                None => fs::read_file(bs, &digest_path),
+            };
+            match contents {
+                Ok(contents) => {
+                    let digest = core::str::from_utf8(&contents)
+                        .ok()
+                        .and_then(|text| text.split_whitespace().next())
+                        .and_then(Digest::parse);
+                    match digest {
+                        Some(digest) => digest,
+                        None => {
+                            error!("{} doesn't start with a SHA-256 digest", digest_path);
+                            return false;
+                        }
+                    }
+                }
+                Err(Status::NOT_FOUND) => {
+                    info!("No SHA-256 digest for {}, not verifying it", path);
+                    return true;
+                }
+                Err(status) => {
+                    error!("Failed to read {}: {:?}", digest_path, status);
+                    return false;
+                }
+            }
+        }
+    };
+
+    let actual = sha256::sha256(data);
+    if actual != expected {
+        error!("{} is corrupted, refusing to boot", path);
+        error!("Expected SHA-256 {}", expected);
+        error!("Actual SHA-256   {}", actual);
+        return false;
+    }
+    info!("{} matches its SHA-256 digest", path);
+    true
+}
+
+/// Maps the framebuffer of the graphics mode that was set and describes it for the kernel
+fn map_framebuffer(
+    page_tables: &mut KernelPageTables,
+    gop: &mut GraphicsOutput,
+    mode: &Mode,
+) -> FramebufferInfo {
+    let physical_base = gop.frame_buffer().as_mut_ptr() as u64;
+    let size = gop.frame_buffer().size() as u64;
+    let base = page_tables.map_framebuffer(physical_base, size);
+    // Modes without a supported format were never considered
+    let (pixel_format, masks) = graphics::framebuffer_format(mode.info()).unwrap();
+    let (width, height) = mode.info().resolution();
+    FramebufferInfo {
+        base,
+        physical_base,
+        size,
+        width: width as u64,
+        height: height as u64,
+        stride: mode.info().stride() as u64,
+        pixel_format,
+        masks,
+    }
+}
+
+/// Looks up the ACPI and SMBIOS tables in the configuration table
+/// The system table pointer is filled in after exiting boot services
+fn firmware_tables(st: &SystemTable<Boot>) -> FirmwareTables {
+    let find = |guid| {
+        st.config_table()
+            .iter()
+            .find(|entry| entry.guid == guid)
+            .map_or(0, |entry| entry.address as u64)
+    };
+    let mut acpi_rsdp = find(ACPI2_GUID);
+    if acpi_rsdp == 0 {
+        acpi_rsdp = find(ACPI_GUID);
+    }
+    let tables = FirmwareTables {
+        acpi_rsdp,
+        smbios3_entry_point: find(SMBIOS3_GUID),
+        system_table: 0,
+    };
+    info!(
+        "ACPI RSDP at {:X}, SMBIOS 3 entry point at {:X}",
+        tables.acpi_rsdp, tables.smbios3_entry_point
+    );
+    tables
+}
+
+/// Allocates `size` bytes of kernel memory for the kernel's stack and maps them,
+/// the firmware's stack is in boot services memory which the kernel is free to reuse
+fn allocate_kernel_stack(
+    bs: &BootServices,
+    page_tables: &mut KernelPageTables,
+    size: u64,
+) -> VirtualRange {
+    let page_count = (size / 0x1000) as usize;
+    let phys_start = bs
+        .allocate_pages(AllocateType::AnyPages, KERNEL_MEMORY_TYPE, page_count)
+        .unwrap_or_else(|err| {
+            panic!(
+                "Can't allocate {} pages for the kernel stack: {:?}",
+                page_count,
+                err.status()
+            )
+        })
+        .unwrap();
+    let stack = page_tables.map_kernel_stack(phys_start, size);
+    info!(
+        "Kernel stack at {:X}-{:X} ({} KiB)",
+        stack.start,
+        stack.end,
+        size / 1024
+    );
+    stack
+}
+
+/// Where the kernel ended up in memory
+struct LoadedKernel {
+    /// Virtual address of the entry point
+    entry: u64,
+    /// Physical memory the kernel's segments were copied into
+    image: PhysicalRange,
+    /// Difference between the addresses the kernel runs at and the ones it was linked at
+    slide: u64,
+}
+
+/// Copies all the program segments in the kernel elf file into newly allocated memory
+/// and maps them at their virtual addresses in the kernel's page tables
+///
+/// Position independent kernels are moved to a random address and relocated,
+/// everything else is mapped at the addresses it was linked at.
+/// The whole image is loaded into one physically contiguous allocation,
+/// so boot fails right here if the firmware doesn't have enough memory for it
+unsafe fn copy_kernel_segments(
+    bs: &BootServices,
+    kernel: &ElfFile,
+    page_tables: &mut KernelPageTables,
+) -> LoadedKernel {
+    // Find out how much virtual memory the kernel spans, rounded out to whole pages
+    let mut virt_start = u64::MAX;
+    let mut virt_end = 0;
+    // Parsing made sure every alignment is a power of two, or 0 or 1 for none,
+    // and that the rounded up end of every segment fits into the address space
+    let mut align = 0x1000;
+    for segment in kernel.loadable_segments() {
+        let end = segment
+            .p_vaddr
+            .checked_add(segment.p_memsz)
+            .and_then(|end| end.checked_add(0xFFF))
+            .expect("Segment end overflows");
+        virt_start = virt_start.min(segment.p_vaddr & !0xFFF);
+        virt_end = virt_end.max(end & !0xFFF);
+        align = align.max(segment.p_align);
+    }
+    let slide = if kernel.is_position_independent() {
+        // Moving by a multiple of the largest alignment keeps every segment aligned
+        let aligned_start = virt_start & !(align - 1);
+        let base = kaslr::choose_kernel_base(bs, virt_end - aligned_start, align);
+        base.wrapping_sub(aligned_start)
+    } else {
+        0
+    };
+    let page_count = ((virt_end - virt_start) / 0x1000) as usize;
+    let phys_start = bs
+        .allocate_pages(AllocateType::AnyPages, KERNEL_MEMORY_TYPE, page_count)
+        .unwrap_or_else(|err| {
+            panic!(
+                "Can't allocate {} pages to load the kernel into: {:?}",
+                page_count,
+                err.status()
+            )
+        })
+        .unwrap();
+    // Everything that isn't copied from the file (like .bss) has to be zero
+    ptr::write_bytes(phys_start as *mut u8, 0, page_count * 0x1000);
+
+    // Load each entry
+    for segment in kernel.loadable_segments() {
+        // Where the segment ends up in physical and virtual memory
+        let phys_addr = phys_start + (segment.p_vaddr - virt_start);
+        let virt_addr = segment.p_vaddr.wrapping_add(slide);
+        info!(
+            "Writing segment of size {} from {:X} to {:X} (mapped at {:X})",
+            segment.p_memsz, segment.p_offset, phys_addr, virt_addr
+        );
+        // Copy the segment from the file buffer to the memory address
+        let data = kernel.segment_data(&segment);
+        ptr::copy(data.as_ptr(), phys_addr as *mut u8, data.len());
+
+        // Parsing made sure no segment is both writable and executable
+        let mut page_flags = PageTableFlags::empty();
+        if segment.is_writable() {
+            page_flags |= PageTableFlags::WRITABLE;
+        }
+        if !segment.is_executable() {
+            page_flags |= PageTableFlags::NO_EXECUTE;
+        }
+        if segment.p_memsz > 0 {
+            page_tables.map_range(virt_addr, phys_addr, segment.p_memsz, page_flags);
         }
     }
-    // Return the kernel entry's address in memory
-    kernel_entry
+
+    // Every relocation is R_X86_64_RELATIVE inside of a loaded segment, which was checked
+    // when parsing, so all that's left is to write the addend moved by the slide
+    for rela in kernel.relocations() {
+        let target = phys_start + (rela.r_offset - virt_start);
+        ptr::write_unaligned(
+            target as *mut u64,
+            (rela.r_addend as u64).wrapping_add(slide),
+        );
+    }
+
+    LoadedKernel {
+        entry: kernel.entry_point().wrapping_add(slide),
+        image: PhysicalRange {
+            start: phys_start,
+            end: phys_start + (virt_end - virt_start),
+        },
+        slide,
+    }
 }
@@ -0,0 +1,76 @@
+use boot_info::{MemoryRegion, MemoryRegionKind};
+use core::mem;
+use uefi::prelude::*;
+use uefi::table::boot::{MemoryDescriptor, MemoryType};
+
+/// Memory type of the pages the kernel's segments are loaded into,
+/// so they can be told apart from the bootloader's own memory in the memory map
+/// Values starting at 0x80000000 are reserved for use by OS loaders
+pub const KERNEL_MEMORY_TYPE: MemoryType = MemoryType(0x8000_0000);
+/// Memory type of the pages the initial ramdisk is loaded into
+pub const INITRD_MEMORY_TYPE: MemoryType = MemoryType(0x8000_0001);
+
+/// Converts a UEFI memory type into the simpler kind the kernel understands
+fn region_kind(ty: MemoryType) -> MemoryRegionKind {
+    match ty {
+        MemoryType::CONVENTIONAL => MemoryRegionKind::Usable,
+        MemoryType::BOOT_SERVICES_CODE | MemoryType::BOOT_SERVICES_DATA => {
+            MemoryRegionKind::BootServices
+        }
+        MemoryType::LOADER_CODE | MemoryType::LOADER_DATA => MemoryRegionKind::Loader,
+        KERNEL_MEMORY_TYPE => MemoryRegionKind::Kernel,
+        INITRD_MEMORY_TYPE => MemoryRegionKind::Initrd,
+        MemoryType::ACPI_RECLAIM => MemoryRegionKind::AcpiReclaimable,
+        // Runtime services, ACPI NVS, MMIO, unusable and unknown memory
+        _ => MemoryRegionKind::Reserved,
+    }
+}
+
+/// Writes the UEFI memory map into `regions` as a list of kernel memory regions
+/// and returns how many were written
+///
+/// This runs after exiting boot services, so it can't allocate,
+/// `regions` has to be large enough to fit one region per descriptor.
+/// Neighbouring descriptors of the same kind are merged into one region.
+pub fn convert_memory_map<'a>(
+    descriptors: impl Iterator<Item = &'a MemoryDescriptor>,
+    regions: &mut [MemoryRegion],
+) -> usize {
+    let mut len = 0;
+    for descriptor in descriptors {
+        let region = MemoryRegion {
+            start: descriptor.phys_start,
+            page_count: descriptor.page_count,
+            kind: region_kind(descriptor.ty),
+        };
+        if len > 0 {
+            let last = &mut regions[len - 1];
+            if last.kind == region.kind && last.end() == region.start {
+                last.page_count += region.page_count;
+                continue;
+            }
+        }
+        regions[len] = region;
+        len += 1;
+    }
+    len
+}
+
+/// Returns the end of the highest physical memory the firmware knows about, ignoring MMIO
+/// This is how much memory the kernel needs to be able to access through its physical mapping
+pub fn max_physical_address(bs: &BootServices) -> u64 {
+    let mut mmap_buf = vec![0; bs.memory_map_size() + 8 * mem::size_of::<MemoryDescriptor>()];
+    let (_key, descriptors) = bs
+        .memory_map(&mut mmap_buf)
+        .expect_success("Failed to get the memory map");
+    descriptors
+        .filter(|descriptor| {
+            !matches!(
+                descriptor.ty,
+                MemoryType::MMIO | MemoryType::MMIO_PORT_SPACE
+            )
+        })
+        .map(|descriptor| descriptor.phys_start + descriptor.page_count * 4096)
+        .max()
+        .unwrap_or(0)
+}
@@ -0,0 +1,146 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use uefi::prelude::*;
+use uefi::proto::console::text::{Input, Key, Output, ScanCode};
+
+use crate::config::{BootConfig, BootEntry};
+
+/// How long to wait between checks for a key press, in microseconds
+const POLL_INTERVAL: u64 = 10_000;
+
+/// Shows the boot menu and returns the entry that should be booted,
+/// with the command line the user typed in if it was edited
+///
+/// The default entry is booted when the timeout runs out, pressing any key stops the countdown.
+/// With a timeout of 0 the menu isn't shown at all
+pub fn select_entry(st: &SystemTable<Boot>, config: &BootConfig) -> BootEntry {
+    let mut selected = config.default_entry;
+    if config.timeout == 0 {
+        return config.entries[selected].clone();
+    }
+    let bs = st.boot_services();
+    let stdin = st.stdin();
+    let stdout = st.stdout();
+    let mut remaining = Some(config.timeout * 1_000_000);
+
+    loop {
+        draw(stdout, config, selected, remaining);
+        let key = loop {
+            if let Some(key) = read_key(stdin) {
+                break key;
+            }
+            if let Some(time) = remaining {
+                if time == 0 {
+                    return config.entries[selected].clone();
+                }
+                remaining = Some(time - POLL_INTERVAL.min(time));
+                // Only redraw when the number of seconds shown changes
+                if time % 1_000_000 == 0 {
+                    draw(stdout, config, selected, remaining);
+                }
+            }
+            bs.stall(POLL_INTERVAL as usize);
+        };
+        remaining = None;
+
+        match key {
+            Key::Special(ScanCode::UP) => {
+                selected = selected.checked_sub(1).unwrap_or(config.entries.len() - 1)
+            }
+            Key::Special(ScanCode::DOWN) => selected = (selected + 1) % config.entries.len(),
+            Key::Printable(c) if char::from(c) == '\r' => return config.entries[selected].clone(),
+            Key::Printable(c) if char::from(c) == 'e' => {
+                let mut entry = config.entries[selected].clone();
+                if let Some(cmdline) = edit_line(bs, stdin, stdout, &entry.cmdline) {
+                    entry.cmdline = cmdline;
+                    return entry;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(stdout: &mut Output, config: &BootConfig, selected: usize, remaining: Option<u64>) {
+    stdout.clear().expect_success("Failed to clear the screen");
+    writeln!(stdout, "Boot menu\n").unwrap();
+    for (i, entry) in config.entries.iter().enumerate() {
+        let marker = if i == selected { '>' } else { ' ' };
+        writeln!(stdout, " {} {}", marker, entry.title).unwrap();
+    }
+    writeln!(
+        stdout,
+        "\nUp/Down to select, Enter to boot, e to edit the command line"
+    )
+    .unwrap();
+    if let Some(time) = remaining {
+        let seconds = (time + 999_999) / 1_000_000;
+        writeln!(stdout, "Booting the selected entry in {} s", seconds).unwrap();
+    }
+}
+
+/// Lets the user edit a line of text, returns `None` if editing was cancelled with escape
+fn edit_line(
+    bs: &BootServices,
+    stdin: &mut Input,
+    stdout: &mut Output,
+    text: &str,
+) -> Option<String> {
+    let mut line: Vec<char> = text.chars().collect();
+    let mut cursor = line.len();
+    writeln!(stdout, "\nCommand line (Enter to boot, Esc to cancel):").unwrap();
+    let (_, row) = stdout.cursor_position();
+    let mut drawn = 0;
+    loop {
+        // Spaces over the end of the old line clear characters that were deleted
+        stdout
+            .set_cursor_position(0, row)
+            .expect_success("Failed to move the cursor");
+        let text: String = line.iter().collect();
+        write!(stdout, "> {}", text).unwrap();
+        for _ in line.len()..drawn {
+            write!(stdout, " ").unwrap();
+        }
+        drawn = line.len();
+        stdout
+            .set_cursor_position(2 + cursor, row)
+            .expect_success("Failed to move the cursor");
+
+        let key = loop {
+            if let Some(key) = read_key(stdin) {
+                break key;
+            }
+            bs.stall(POLL_INTERVAL as usize);
+        };
+        match key {
+            Key::Printable(c) => match char::from(c) {
+                '\r' => return Some(line.into_iter().collect()),
+                // Backspace
+                '\u{8}' if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                c if !c.is_control() => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                _ => {}
+            },
+            Key::Special(ScanCode::ESCAPE) => return None,
+            Key::Special(ScanCode::LEFT) => cursor = cursor.saturating_sub(1),
+            Key::Special(ScanCode::RIGHT) => cursor = (cursor + 1).min(line.len()),
+            Key::Special(ScanCode::HOME) => cursor = 0,
+            Key::Special(ScanCode::END) => cursor = line.len(),
+            Key::Special(ScanCode::DELETE) if cursor < line.len() => {
+                line.remove(cursor);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the next key press if there is one, without waiting
+fn read_key(stdin: &mut Input) -> Option<Key> {
+    stdin.read_key().ok()?.unwrap()
+}
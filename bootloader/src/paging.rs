@@ -0,0 +1,212 @@
+use boot_info::VirtualRange;
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryType};
+use x86_64::instructions::tables::{lgdt, DescriptorTablePointer};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::{MapToError, Translate, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame,
+    Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// All of physical memory is mapped starting here in the kernel's address space
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0xffff_8000_0000_0000;
+/// Virtual address the framebuffer is mapped at
+pub const FRAMEBUFFER_ADDRESS: u64 = 0xffff_a000_0000_0000;
+/// Virtual address of the guard page below the kernel's stack, the stack starts one page above
+pub const KERNEL_STACK_ADDRESS: u64 = 0xffff_b000_0000_0000;
+
+/// Hands out page table frames from the firmware, as loader data so the kernel keeps them
+struct BootFrameAllocator<'a>(&'a BootServices);
+
+unsafe impl FrameAllocator<Size4KiB> for BootFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let address = self
+            .0
+            .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1)
+            .ok()?
+            .unwrap();
+        Some(PhysFrame::containing_address(PhysAddr::new(address)))
+    }
+}
+
+/// The page tables the kernel is started with
+///
+/// They are built while the firmware's identity mapping is still active,
+/// so every table is accessed at its physical address
+pub struct KernelPageTables<'a> {
+    pml4: PhysFrame,
+    mapper: OffsetPageTable<'static>,
+    allocator: BootFrameAllocator<'a>,
+}
+
+impl<'a> KernelPageTables<'a> {
+    pub fn new(bs: &'a BootServices) -> Self {
+        let mut allocator = BootFrameAllocator(bs);
+        let pml4 = allocator
+            .allocate_frame()
+            .expect("Failed to allocate the kernel's page tables");
+        let mapper = unsafe {
+            let table = &mut *(pml4.start_address().as_u64() as *mut PageTable);
+            table.zero();
+            OffsetPageTable::new(table, VirtAddr::new(0))
+        };
+        Self {
+            pml4,
+            mapper,
+            allocator,
+        }
+    }
+
+    /// Maps `size` bytes starting at `virt_start` to the physical memory at `phys_start`
+    ///
+    /// Pages that are already mapped, because two kernel segments share a page,
+    /// get the most permissive combination of both flags, unless that would make
+    /// the page both writable and executable
+    pub fn map_range(
+        &mut self,
+        virt_start: u64,
+        phys_start: u64,
+        size: u64,
+        flags: PageTableFlags,
+    ) {
+        let first_page = Page::<Size4KiB>::containing_address(VirtAddr::new(virt_start));
+        let last_page = Page::containing_address(VirtAddr::new(virt_start + size - 1));
+        let phys_start = phys_start - (virt_start - first_page.start_address().as_u64());
+        for (i, page) in Page::range_inclusive(first_page, last_page).enumerate() {
+            let frame = PhysFrame::containing_address(PhysAddr::new(
+                phys_start + i as u64 * Size4KiB::SIZE,
+            ));
+            let flags = flags | PageTableFlags::PRESENT;
+            match unsafe { self.mapper.map_to(page, frame, flags, &mut self.allocator) } {
+                Ok(flush) => flush.ignore(),
+                Err(MapToError::PageAlreadyMapped(_)) => self.merge_flags(page, flags),
+                Err(err) => panic!("Failed to map {:?}: {:?}", page, err),
+            }
+        }
+    }
+
+    /// Maps `[0, max_address)` at `PHYSICAL_MEMORY_OFFSET` with 2MiB pages
+    pub fn map_physical_memory(&mut self, max_address: u64) {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let last_frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(max_address - 1));
+        for frame in
+            PhysFrame::range_inclusive(PhysFrame::containing_address(PhysAddr::new(0)), last_frame)
+        {
+            let page = Page::<Size2MiB>::containing_address(VirtAddr::new(
+                PHYSICAL_MEMORY_OFFSET + frame.start_address().as_u64(),
+            ));
+            unsafe {
+                self.mapper
+                    .map_to(page, frame, flags, &mut self.allocator)
+                    .expect("Failed to map physical memory")
+                    .ignore();
+            }
+        }
+    }
+
+    /// Maps the framebuffer at `FRAMEBUFFER_ADDRESS` and returns that address
+    pub fn map_framebuffer(&mut self, phys_start: u64, size: u64) -> u64 {
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        self.map_range(FRAMEBUFFER_ADDRESS, phys_start, size, flags);
+        FRAMEBUFFER_ADDRESS + (phys_start & 0xFFF)
+    }
+
+    /// Maps the kernel's stack above an unmapped guard page at `KERNEL_STACK_ADDRESS`
+    /// and returns the virtual range of the stack
+    pub fn map_kernel_stack(&mut self, phys_start: u64, size: u64) -> VirtualRange {
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let start = KERNEL_STACK_ADDRESS + Size4KiB::SIZE;
+        self.map_range(start, phys_start, size, flags);
+        VirtualRange {
+            start,
+            end: start + size,
+        }
+    }
+
+    /// Identity maps the pages of `jump_to_kernel`, which keeps running after switching to
+    /// the new page tables. Two pages are mapped in case the function crosses a page boundary
+    pub fn map_trampoline(&mut self) {
+        let start = jump_to_kernel as usize as u64;
+        self.map_range(start, start, 2 * Size4KiB::SIZE, PageTableFlags::empty());
+    }
+
+    pub fn pml4_address(&self) -> u64 {
+        self.pml4.start_address().as_u64()
+    }
+
+    fn merge_flags(&mut self, page: Page, flags: PageTableFlags) {
+        let existing = match self.mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags,
+            _ => unreachable!(),
+        };
+        let mut merged = existing | flags;
+        // Only keep the page non executable if neither mapping needs to execute from it
+        if !(existing & flags).contains(PageTableFlags::NO_EXECUTE) {
+            merged.remove(PageTableFlags::NO_EXECUTE);
+        }
+        if merged.contains(PageTableFlags::WRITABLE) && !merged.contains(PageTableFlags::NO_EXECUTE)
+        {
+            panic!(
+                "{:?} would be writable and executable, segments need to be page aligned",
+                page
+            );
+        }
+        unsafe {
+            self.mapper
+                .update_flags(page, merged)
+                .expect("Failed to update page flags")
+                .ignore();
+        }
+    }
+}
+
+/// Switches to the kernel's page tables and stack and calls its entry point
+///
+/// # Safety
+/// Must be called after exiting boot services, since the firmware won't work anymore
+/// once its identity mapping is gone. The page tables have to map the kernel,
+/// all of physical memory, the trampoline and the stack that ends at `stack_top`
+pub unsafe fn enter_kernel(page_tables: u64, entry: u64, boot_info: u64, stack_top: u64) -> ! {
+    x86_64::instructions::interrupts::disable();
+    // The kernel's page tables use the NX bit, and write protection should apply in ring 0 too
+    Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    // The firmware's GDT stays in use until the kernel loads its own, so point the GDTR
+    // at its address in the physical memory mapping since the identity mapping is going away
+    let mut gdt = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    };
+    asm!(
+        "sgdt [{}]",
+        in(reg) &mut gdt as *mut DescriptorTablePointer,
+        options(nostack, preserves_flags)
+    );
+    let base = gdt.base;
+    gdt.base = base + PHYSICAL_MEMORY_OFFSET;
+    lgdt(&gdt);
+
+    jump_to_kernel(page_tables, entry, boot_info, stack_top)
+}
+
+/// Loads the new page tables, switches to the kernel's stack and calls the kernel,
+/// this has to be identity mapped in the new page tables
+#[inline(never)]
+unsafe fn jump_to_kernel(page_tables: u64, entry: u64, boot_info: u64, stack_top: u64) -> ! {
+    asm!(
+        "mov cr3, {page_tables}",
+        // The stack is only mapped in the kernel's page tables, and nothing is pushed
+        // before the call, which leaves the stack aligned the way the ABI expects
+        "mov rsp, {stack_top}",
+        "and rsp, -16",
+        "call {entry}",
+        page_tables = in(reg) page_tables,
+        stack_top = in(reg) stack_top,
+        entry = in(reg) entry,
+        in("rdi") boot_info,
+        options(noreturn)
+    )
+}
@@ -0,0 +1,215 @@
+use alloc::vec::Vec;
+use boot_info::PhysicalRange;
+use core::ffi::c_void;
+use core::{ptr, slice};
+use log::info;
+use uefi::prelude::*;
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::Protocol;
+use uefi::table::boot::{AllocateType, MemoryType};
+use uefi::unsafe_guid;
+
+// EFI_PXE_BASE_CODE_TFTP_OPCODE values
+const TFTP_GET_FILE_SIZE: u32 = 1;
+const TFTP_READ_FILE: u32 = 2;
+
+/// EFI_IP_ADDRESS, big enough for IPv6 but only the first 4 bytes are used here
+#[repr(C, align(4))]
+#[derive(Clone, Copy)]
+struct IpAddress([u8; 16]);
+
+/// EFI_PXE_BASE_CODE_PACKET, a raw DHCP packet
+#[repr(C, align(4))]
+struct Packet([u8; 1472]);
+
+/// The start of EFI_PXE_BASE_CODE_MODE, up to the DHCP acknowledgement
+#[repr(C)]
+struct BaseCodeMode {
+    _started: u8,
+    /// From Ipv6Available to DhcpDiscoverValid
+    _flags: [u8; 8],
+    dhcp_ack_received: u8,
+    /// From ProxyOfferReceived to MakeCallbacks, then TTL and ToS
+    _more_flags: [u8; 9],
+    station_ip: IpAddress,
+    _subnet_mask: IpAddress,
+    _dhcp_discover: Packet,
+    dhcp_ack: Packet,
+}
+
+/// EFI_PXE_BASE_CODE_PROTOCOL, which uefi-rs doesn't have yet
+#[repr(C)]
+#[unsafe_guid("03c4e603-ac28-11d3-9a2d-0090273fc14d")]
+#[derive(Protocol)]
+struct BaseCode {
+    _revision: u64,
+    start: extern "efiapi" fn(this: &mut BaseCode, use_ipv6: bool) -> Status,
+    _stop: usize,
+    dhcp: extern "efiapi" fn(this: &mut BaseCode, sort_offers: bool) -> Status,
+    _discover: usize,
+    mtftp: extern "efiapi" fn(
+        this: &mut BaseCode,
+        operation: u32,
+        buffer: *mut c_void,
+        overwrite: bool,
+        buffer_size: &mut u64,
+        block_size: *const usize,
+        server_ip: &IpAddress,
+        filename: *const u8,
+        info: *const c_void,
+        dont_use_buffer: bool,
+    ) -> Status,
+    /// UdpWrite, UdpRead, SetIpFilter, Arp, SetParameters, SetStationIp and SetPackets
+    _other_functions: [usize; 7],
+    mode: *const BaseCodeMode,
+}
+
+/// Reads files from a TFTP server through the firmware's PXE support
+pub struct Tftp<'a> {
+    base_code: &'a mut BaseCode,
+    server: IpAddress,
+}
+
+impl<'a> Tftp<'a> {
+    /// Configures the network with DHCP unless the firmware already did when it booted
+    /// over the network, and uses `server` or the boot server DHCP named if it's `None`
+    pub fn connect(
+        bs: &'a BootServices,
+        image: Handle,
+        server: Option<[u8; 4]>,
+    ) -> Result<Self, Status> {
+        // After a network boot, the bootloader's device is the network card that was used
+        let device = bs
+            .handle_protocol::<LoadedImage>(image)
+            .ok()
+            .map(|loaded_image| unsafe { &*loaded_image.unwrap().get() }.device());
+        let base_code = match device.and_then(|device| bs.handle_protocol::<BaseCode>(device).ok())
+        {
+            Some(base_code) => base_code,
+            None => bs
+                .locate_protocol::<BaseCode>()
+                .map_err(|err| err.status())?,
+        };
+        let base_code = unsafe { &mut *base_code.unwrap().get() };
+
+        let status = (base_code.start)(base_code, false);
+        if status != Status::SUCCESS && status != Status::ALREADY_STARTED {
+            return Err(status);
+        }
+        if unsafe { (*base_code.mode).dhcp_ack_received } == 0 {
+            info!("Configuring the network with DHCP");
+            let status = (base_code.dhcp)(base_code, false);
+            if status != Status::SUCCESS {
+                return Err(status);
+            }
+        }
+        let mode = unsafe { &*base_code.mode };
+        let ip = mode.station_ip.0;
+        info!("IP address {}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3]);
+
+        // The next server field of the DHCP reply
+        let server = server.unwrap_or_else(|| {
+            let mut next_server = [0; 4];
+            next_server.copy_from_slice(&mode.dhcp_ack.0[20..24]);
+            next_server
+        });
+        if server == [0; 4] {
+            return Err(Status::NOT_FOUND);
+        }
+        info!(
+            "Using TFTP server {}.{}.{}.{}",
+            server[0], server[1], server[2], server[3]
+        );
+        let mut address = [0; 16];
+        address[..4].copy_from_slice(&server);
+        Ok(Self {
+            base_code,
+            server: IpAddress(address),
+        })
+    }
+
+    /// Downloads a whole file
+    ///
+    /// Returns the firmware's status if the file can't be read, `NOT_FOUND` if the server
+    /// reported an error for it, which usually means it doesn't exist
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, Status> {
+        let size = self.file_size(path)?;
+        let mut data = vec![0; size as usize];
+        self.read_into(path, &mut data)?;
+        Ok(data)
+    }
+
+    /// Downloads a whole file into newly allocated pages of the given type,
+    /// for files that have to stay in memory after exiting boot services
+    pub fn read_file_to_pages(
+        &mut self,
+        bs: &BootServices,
+        path: &str,
+        memory_type: MemoryType,
+    ) -> Result<PhysicalRange, Status> {
+        let size = self.file_size(path)?;
+        if size == 0 {
+            return Ok(PhysicalRange { start: 0, end: 0 });
+        }
+        let page_count = ((size + 0xFFF) / 0x1000) as usize;
+        let start = bs
+            .allocate_pages(AllocateType::AnyPages, memory_type, page_count)
+            .map_err(|err| err.status())?
+            .unwrap();
+        let data = unsafe { slice::from_raw_parts_mut(start as *mut u8, size as usize) };
+        if let Err(status) = self.read_into(path, data) {
+            bs.free_pages(start, page_count).unwrap().unwrap();
+            return Err(status);
+        }
+        Ok(PhysicalRange {
+            start,
+            end: start + size,
+        })
+    }
+
+    fn file_size(&mut self, path: &str) -> Result<u64, Status> {
+        let mut size = 0;
+        self.mtftp(TFTP_GET_FILE_SIZE, path, ptr::null_mut(), &mut size)?;
+        Ok(size)
+    }
+
+    fn read_into(&mut self, path: &str, buffer: &mut [u8]) -> Result<(), Status> {
+        let mut size = buffer.len() as u64;
+        self.mtftp(
+            TFTP_READ_FILE,
+            path,
+            buffer.as_mut_ptr() as *mut c_void,
+            &mut size,
+        )
+    }
+
+    fn mtftp(
+        &mut self,
+        operation: u32,
+        path: &str,
+        buffer: *mut c_void,
+        size: &mut u64,
+    ) -> Result<(), Status> {
+        // The file name is a zero terminated ASCII string
+        let mut filename: Vec<u8> = path.bytes().collect();
+        filename.push(0);
+        let mtftp = self.base_code.mtftp;
+        let status = mtftp(
+            self.base_code,
+            operation,
+            buffer,
+            false,
+            size,
+            ptr::null(),
+            &self.server,
+            filename.as_ptr(),
+            ptr::null(),
+            false,
+        );
+        match status {
+            Status::SUCCESS => Ok(()),
+            Status::TFTP_ERROR => Err(Status::NOT_FOUND),
+            status => Err(status),
+        }
+    }
+}
@@ -3,24 +3,93 @@
 #![feature(asm)]
 #![feature(naked_functions)]
 #![feature(core_intrinsics)]
+#![feature(alloc_error_handler)]
+extern crate alloc;
 mod utils;
-use utils::framebuffer::{set_framebuffer, FramebufferInfo};
+use boot_info::BootInfo;
+use utils::allocator;
+use utils::boot_time;
+use utils::bootlog;
+use utils::cmdline;
+use utils::console;
+use utils::framebuffer::set_framebuffer;
+use utils::initrd;
 use utils::interrupts;
+use utils::memory;
+use utils::tsc;
 
+use core::alloc::Layout;
 use core::panic::PanicInfo;
 
 #[no_mangle]
-pub extern "C" fn _start(fb_info: FramebufferInfo) -> ! {
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    let kernel_entry = tsc::read();
+    // Nothing in the boot info can be trusted if it was made by a different version of the
+    // bootloader, and without it we don't have a framebuffer, so complain on the serial port
+    if let Err(err) = boot_info.validate() {
+        serial_println!("Refusing to boot: {}", err);
+        loop {}
+    }
     // Initialize interrupts
     interrupts::init();
-    // The bootloader passes in the fb_info struct when starting the kernel
+    cmdline::init(boot_info.cmdline());
+    // The bootloader passes in the framebuffer info when starting the kernel
     // We use it to set the global framebuffer so that print! and println! work
-    set_framebuffer(fb_info);
+    // With console=serial, or without a framebuffer, they go to the serial port instead
+    if !boot_info.framebuffer.is_present() {
+        serial_println!("No framebuffer, using the serial console");
+    } else if cmdline::get("console") != Some("serial") {
+        set_framebuffer(boot_info.framebuffer);
+    }
+    // What the bootloader logged was only on the console the framebuffer just replaced
+    bootlog::init(boot_info.bootloader_log());
+    bootlog::show();
+    println!("Command line: {}", cmdline::cmdline());
+    // Needed to match addresses in crash output with the kernel binary
+    println!("Kernel slide: {:#x}", boot_info.kernel_slide);
+    let stack = boot_info.kernel_stack;
+    println!(
+        "Kernel stack: {:#x}-{:#x} ({} KiB)",
+        stack.start,
+        stack.end,
+        stack.len() / 1024
+    );
+    // Give all the usable memory from the memory map to the frame allocator
+    memory::init(boot_info);
+    let frames = memory::frame_stats();
+    println!(
+        "Physical frames: {} free, {} used ({} peak) of {}",
+        frames.free, frames.used, frames.peak_used, frames.total
+    );
+    // Map the kernel heap so that Box, Vec and friends work
+    allocator::init().expect("Failed to map the kernel heap");
+    println!("{}", allocator::heap_stats());
+    let firmware = boot_info.firmware_tables;
+    println!(
+        "ACPI RSDP: {:#x}, SMBIOS 3: {:#x}, EFI system table: {:#x}",
+        firmware.acpi_rsdp, firmware.smbios3_entry_point, firmware.system_table
+    );
+    // Time is measured with the TSC, the bootloader only knows its raw value
+    let frequency = tsc::calibrate();
+    println!("TSC frequency: {} MHz", frequency / 1_000_000);
+    boot_time::init(boot_info.timestamps, kernel_entry);
+    boot_time::show();
+    // The initial ramdisk is the first filesystem, no disk driver needed
+    if initrd::init(boot_info) {
+        let files = initrd::archive().unwrap().entries().count();
+        println!("Initrd: {} entries", files);
+    } else {
+        println!("No initrd");
+    }
     // unsafe { asm!("ud2") };
-    unsafe { *(0xd25235dbeaf as *mut u64) = 42 };
+    // The page fault handler returns, so the write faults again and again and nothing after it
+    // runs, which is why it only happens when asked for on the command line
+    if cmdline::contains("page_fault_test") {
+        unsafe { *(0xd25235dbeaf as *mut u64) = 42 };
+    }
 
     println!("Hello, {}", "World!");
-    loop {}
+    console::run()
 }
 
 #[panic_handler]
@@ -28,3 +97,18 @@ fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
     loop {}
 }
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    let heap = allocator::heap_stats();
+    println!(
+        "ALLOCATION ERROR: failed to allocate {} bytes aligned to {}\
+        \n{}\nlargest free block: {} bytes, heap can grow to {} bytes",
+        layout.size(),
+        layout.align(),
+        heap,
+        heap.largest_free_block,
+        allocator::HEAP_MAX_SIZE
+    );
+    loop {}
+}
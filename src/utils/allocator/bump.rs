@@ -0,0 +1,60 @@
+use core::alloc::Layout;
+use core::ptr;
+
+use super::{align_up, HeapBackend};
+
+/// Hands out memory by moving a pointer forward, memory is only reused
+/// once every allocation has been freed
+pub struct BumpAllocator {
+    heap_end: usize,
+    heap_start: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl BumpAllocator {
+    pub const fn new() -> Self {
+        Self {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+}
+
+impl HeapBackend for BumpAllocator {
+    const NAME: &'static str = "bump";
+
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        self.heap_start = start;
+        self.heap_end = start + size;
+        self.next = start;
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        self.heap_end += size;
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) if end <= self.heap_end => end,
+            _ => return ptr::null_mut(),
+        };
+        self.next = alloc_end;
+        self.allocations += 1;
+        alloc_start as *mut u8
+    }
+
+    unsafe fn deallocate(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
+
+    fn largest_free_block(&self) -> usize {
+        self.heap_end - self.next
+    }
+}
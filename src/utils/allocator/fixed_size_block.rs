@@ -0,0 +1,96 @@
+use core::alloc::Layout;
+use core::{mem, ptr};
+
+use super::linked_list::LinkedListAllocator;
+use super::HeapBackend;
+
+/// The block sizes, each one is also used as the block's alignment so they have to be
+/// powers of two. Anything larger goes straight to the fallback allocator
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct BlockNode {
+    next: *mut BlockNode,
+}
+
+/// Slab style allocator with one free list per block size
+///
+/// Blocks are carved out of the fallback allocator the first time they are needed,
+/// and are kept in their free list instead of being given back when freed
+pub struct FixedSizeBlockAllocator {
+    list_heads: [*mut BlockNode; BLOCK_SIZES.len()],
+    fallback: LinkedListAllocator,
+}
+
+// The lists are only ever accessed behind the heap's mutex
+unsafe impl Send for FixedSizeBlockAllocator {}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        Self {
+            list_heads: [ptr::null_mut(); BLOCK_SIZES.len()],
+            fallback: LinkedListAllocator::new(),
+        }
+    }
+
+    /// Index of the smallest block size that fits the layout
+    fn list_index(layout: &Layout) -> Option<usize> {
+        let required = layout.size().max(layout.align());
+        BLOCK_SIZES.iter().position(|&size| size >= required)
+    }
+}
+
+impl HeapBackend for FixedSizeBlockAllocator {
+    const NAME: &'static str = "fixed size block";
+
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        self.fallback.init(start, size);
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        self.fallback.extend(size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        match Self::list_index(&layout) {
+            Some(index) => {
+                let node = self.list_heads[index];
+                if node.is_null() {
+                    // No free block of this size yet, make a new one
+                    let block_size = BLOCK_SIZES[index];
+                    let block_layout = Layout::from_size_align(block_size, block_size).unwrap();
+                    self.fallback.allocate(block_layout)
+                } else {
+                    self.list_heads[index] = unsafe { (*node).next };
+                    node as *mut u8
+                }
+            }
+            None => self.fallback.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match Self::list_index(&layout) {
+            Some(index) => {
+                // Every block size is large and aligned enough to hold a node
+                debug_assert!(mem::size_of::<BlockNode>() <= BLOCK_SIZES[index]);
+                let node = ptr as *mut BlockNode;
+                node.write(BlockNode {
+                    next: self.list_heads[index],
+                });
+                self.list_heads[index] = node;
+            }
+            None => self.fallback.deallocate(ptr, layout),
+        }
+    }
+
+    fn largest_free_block(&self) -> usize {
+        let largest_block = BLOCK_SIZES
+            .iter()
+            .zip(self.list_heads.iter())
+            .filter(|(_, head)| !head.is_null())
+            .map(|(&size, _)| size)
+            .max()
+            .unwrap_or(0);
+        largest_block.max(self.fallback.largest_free_block())
+    }
+}
@@ -0,0 +1,131 @@
+use core::alloc::Layout;
+use core::{mem, ptr};
+
+use super::{align_up, HeapBackend};
+
+/// Header stored at the start of every free region
+struct ListNode {
+    size: usize,
+    next: *mut ListNode,
+}
+
+/// Keeps the free regions in a list sorted by address, allocates with first fit
+/// and merges neighbouring regions when memory is freed
+pub struct LinkedListAllocator {
+    head: *mut ListNode,
+    heap_end: usize,
+}
+
+// The list is only ever accessed behind the heap's mutex
+unsafe impl Send for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    pub const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+            heap_end: 0,
+        }
+    }
+
+    /// Every block has to be able to hold a list node once it is freed
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let align = layout.align().max(mem::align_of::<ListNode>());
+        let size = align_up(layout.size(), mem::align_of::<ListNode>());
+        (size.max(mem::size_of::<ListNode>()), align)
+    }
+
+    /// Inserts a free region into the list, merging it with the regions right before and after
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut ListNode = ptr::null_mut();
+        let mut current = self.head;
+        while !current.is_null() && (current as usize) < addr {
+            prev = current;
+            current = (*current).next;
+        }
+
+        let mut size = size;
+        let mut next = current;
+        if !current.is_null() && addr + size == current as usize {
+            size += (*current).size;
+            next = (*current).next;
+        }
+        if !prev.is_null() && prev as usize + (*prev).size == addr {
+            (*prev).size += size;
+            (*prev).next = next;
+            return;
+        }
+
+        let node = addr as *mut ListNode;
+        node.write(ListNode { size, next });
+        if prev.is_null() {
+            self.head = node;
+        } else {
+            (*prev).next = node;
+        }
+    }
+}
+
+impl HeapBackend for LinkedListAllocator {
+    const NAME: &'static str = "linked list";
+
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        self.heap_end = start + size;
+        self.add_free_region(start, size);
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        let start = self.heap_end;
+        self.heap_end += size;
+        self.add_free_region(start, size);
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+        let node_size = mem::size_of::<ListNode>();
+        let mut prev: *mut *mut ListNode = &mut self.head;
+        unsafe {
+            while !(*prev).is_null() {
+                let node = *prev;
+                let region_start = node as usize;
+                let region_end = region_start + (*node).size;
+
+                let mut alloc_start = align_up(region_start, align);
+                // A gap in front that is too small for a list node would be lost
+                if alloc_start != region_start && alloc_start - region_start < node_size {
+                    alloc_start = align_up(region_start + node_size, align);
+                }
+                let alloc_end = alloc_start.saturating_add(size);
+                let back = region_end.saturating_sub(alloc_end);
+                if alloc_end <= region_end && (back == 0 || back >= node_size) {
+                    *prev = (*node).next;
+                    if alloc_start != region_start {
+                        self.add_free_region(region_start, alloc_start - region_start);
+                    }
+                    if back != 0 {
+                        self.add_free_region(alloc_end, back);
+                    }
+                    return alloc_start as *mut u8;
+                }
+                prev = &mut (*node).next;
+            }
+        }
+        ptr::null_mut()
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.add_free_region(ptr as usize, size);
+    }
+
+    fn largest_free_block(&self) -> usize {
+        let mut largest = 0;
+        let mut current = self.head;
+        while !current.is_null() {
+            unsafe {
+                largest = largest.max((*current).size);
+                current = (*current).next;
+            }
+        }
+        largest
+    }
+}
@@ -0,0 +1,252 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::{fmt, ptr};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, Size4KiB};
+use x86_64::VirtAddr;
+
+use super::memory::FRAME_ALLOCATOR;
+use super::paging::{AddressSpace, CacheMode, MapFlags};
+
+#[cfg(feature = "alloc-bump")]
+mod bump;
+#[cfg(feature = "alloc-fixed-size-block")]
+mod fixed_size_block;
+#[cfg(any(feature = "alloc-linked-list", feature = "alloc-fixed-size-block"))]
+mod linked_list;
+
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked-list",
+    feature = "alloc-fixed-size-block"
+)))]
+compile_error!("Select a heap allocator with one of the alloc-* features");
+
+#[cfg(any(
+    all(feature = "alloc-bump", feature = "alloc-linked-list"),
+    all(feature = "alloc-bump", feature = "alloc-fixed-size-block"),
+    all(feature = "alloc-linked-list", feature = "alloc-fixed-size-block")
+))]
+compile_error!("Only one of the alloc-* features can be enabled at a time");
+
+#[cfg(feature = "alloc-bump")]
+type Backend = bump::BumpAllocator;
+#[cfg(feature = "alloc-linked-list")]
+type Backend = linked_list::LinkedListAllocator;
+#[cfg(feature = "alloc-fixed-size-block")]
+type Backend = fixed_size_block::FixedSizeBlockAllocator;
+
+/// Start of the kernel heap, in the higher half between the framebuffer and the kernel image
+pub const HEAP_START: u64 = 0xffff_c000_0000_0000;
+/// How much memory is mapped for the heap at boot
+pub const HEAP_INITIAL_SIZE: u64 = 1024 * 1024;
+/// The heap grows in steps of at least this size
+const HEAP_GROW_STEP: u64 = 256 * 1024;
+/// The heap can never grow past this
+pub const HEAP_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+const PAGE_SIZE: usize = 4096;
+
+/// The interface every allocator design implements, so they can be swapped at build time
+///
+/// The heap is always one contiguous region starting at `HEAP_START`,
+/// which only ever grows at the end
+pub trait HeapBackend {
+    /// Shown in the heap statistics
+    const NAME: &'static str;
+
+    /// # Safety
+    /// The memory region has to be mapped and unused, and this can only be called once
+    unsafe fn init(&mut self, start: usize, size: usize);
+
+    /// Adds `size` bytes directly after the current end of the heap
+    ///
+    /// # Safety
+    /// The new memory has to be mapped and unused
+    unsafe fn extend(&mut self, size: usize);
+
+    /// Returns a null pointer if there is no free block large enough
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+
+    /// # Safety
+    /// The pointer has to come from `allocate` with the same layout
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Size of the largest allocation that would currently succeed without growing the heap
+    fn largest_free_block(&self) -> usize;
+}
+
+#[global_allocator]
+static ALLOCATOR: KernelHeap = KernelHeap(Mutex::new(HeapState {
+    backend: Backend::new(),
+    size: 0,
+    in_use: 0,
+    peak: 0,
+    allocations: 0,
+}));
+
+/// Wraps the selected backend, keeps the statistics and maps more memory when it runs out
+pub struct KernelHeap(Mutex<HeapState>);
+
+struct HeapState {
+    backend: Backend,
+    size: usize,
+    in_use: usize,
+    peak: usize,
+    allocations: usize,
+}
+
+/// Usage statistics of the heap, sizes are in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub allocator: &'static str,
+    /// How much memory is mapped for the heap
+    pub size: usize,
+    /// Sum of the sizes of all live allocations
+    pub in_use: usize,
+    /// The highest `in_use` has ever been
+    pub peak: usize,
+    pub free: usize,
+    pub largest_free_block: usize,
+    /// Number of live allocations
+    pub allocations: usize,
+}
+
+impl HeapStats {
+    /// How much of the free memory can't be used for one big allocation, from 0 to 100
+    pub fn fragmentation(&self) -> usize {
+        if self.free == 0 {
+            return 0;
+        }
+        100 - self.largest_free_block.min(self.free) * 100 / self.free
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} heap: {} bytes in use by {} allocations ({} peak), {} of {} bytes free, \
+            {}% fragmented",
+            self.allocator,
+            self.in_use,
+            self.allocations,
+            self.peak,
+            self.free,
+            self.size,
+            self.fragmentation()
+        )
+    }
+}
+
+/// Maps the initial heap region and hands it to the global allocator
+/// Must be called after the frame allocator is set up
+pub fn init() -> Result<(), MapToError<Size4KiB>> {
+    let mut heap = ALLOCATOR.0.lock();
+    map_heap_pages(HEAP_START, HEAP_INITIAL_SIZE)?;
+    unsafe {
+        heap.backend
+            .init(HEAP_START as usize, HEAP_INITIAL_SIZE as usize)
+    };
+    heap.size = HEAP_INITIAL_SIZE as usize;
+    Ok(())
+}
+
+pub fn heap_stats() -> HeapStats {
+    let heap = ALLOCATOR.0.lock();
+    HeapStats {
+        allocator: Backend::NAME,
+        size: heap.size,
+        in_use: heap.in_use,
+        peak: heap.peak,
+        free: heap.size - heap.in_use,
+        largest_free_block: heap.backend.largest_free_block(),
+        allocations: heap.allocations,
+    }
+}
+
+impl HeapState {
+    fn grow(&mut self, size: u64) -> Result<(), MapToError<Size4KiB>> {
+        let size = align_up(size.max(HEAP_GROW_STEP) as usize, PAGE_SIZE) as u64;
+        if self.size as u64 + size > HEAP_MAX_SIZE {
+            return Err(MapToError::FrameAllocationFailed);
+        }
+        map_heap_pages(HEAP_START + self.size as u64, size)?;
+        unsafe { self.backend.extend(size as usize) };
+        self.size += size as usize;
+        Ok(())
+    }
+}
+
+/// Backs the virtual range with newly allocated frames
+///
+/// If that fails partway, the pages mapped so far are unmapped and their frames freed again,
+/// so the heap stays as it was and a later attempt can map the same range
+fn map_heap_pages(start: u64, size: u64) -> Result<(), MapToError<Size4KiB>> {
+    let first_page = Page::containing_address(VirtAddr::new(start));
+    let last_page = Page::containing_address(VirtAddr::new(start + size - 1));
+    let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+    let mut address_space = AddressSpace::active();
+    for page in Page::range_inclusive(first_page, last_page) {
+        let result = match frame_allocator.allocate_frame() {
+            Some(frame) => {
+                let result = unsafe {
+                    address_space.map(
+                        page,
+                        frame,
+                        MapFlags::WRITABLE | MapFlags::NO_EXECUTE,
+                        CacheMode::WriteBack,
+                        &mut *frame_allocator,
+                    )
+                };
+                if result.is_err() {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+                result
+            }
+            None => Err(MapToError::FrameAllocationFailed),
+        };
+        if let Err(err) = result {
+            for mapped in Page::range(first_page, page) {
+                if let Ok(frame) = address_space.unmap(mapped) {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        let mut ptr = heap.backend.allocate(layout);
+        if ptr.is_null() {
+            // Out of space, try again after growing the heap by enough to fit the allocation
+            let needed = (layout.size() + layout.align()) as u64;
+            if heap.grow(needed).is_err() {
+                return ptr::null_mut();
+            }
+            ptr = heap.backend.allocate(layout);
+        }
+        if !ptr.is_null() {
+            heap.in_use += layout.size();
+            heap.peak = heap.peak.max(heap.in_use);
+            heap.allocations += 1;
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut heap = self.0.lock();
+        heap.backend.deallocate(ptr, layout);
+        heap.in_use -= layout.size();
+        heap.allocations -= 1;
+    }
+}
+
+/// Aligns `addr` upwards to `align`, which has to be a power of two
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
@@ -0,0 +1,77 @@
+use boot_info::BootTimestamps;
+use conquer_once::spin::OnceCell;
+use core::fmt;
+
+use super::tsc;
+use crate::println;
+
+/// The bootloader's timestamps, and the TSC value at the start of the kernel
+static TIMESTAMPS: OnceCell<(BootTimestamps, u64)> = OnceCell::uninit();
+
+/// Keeps the timestamps from the boot info, `kernel_entry` is the TSC read first thing in `_start`
+pub fn init(timestamps: BootTimestamps, kernel_entry: u64) {
+    TIMESTAMPS.init_once(|| (timestamps, kernel_entry));
+}
+
+/// Prints how long each step of booting took, this can be called again any time later
+///
+/// Times are in milliseconds once the TSC is calibrated, and in TSC ticks before that
+pub fn show() {
+    let (timestamps, kernel_entry) = match TIMESTAMPS.get() {
+        Some(timestamps) => *timestamps,
+        None => {
+            println!("Boot times weren't recorded");
+            return;
+        }
+    };
+    let steps = [
+        ("Firmware", 0, timestamps.bootloader_entry),
+        (
+            "Bootloader and boot menu",
+            timestamps.bootloader_entry,
+            timestamps.gop_set,
+        ),
+        (
+            "Reading the kernel",
+            timestamps.gop_set,
+            timestamps.kernel_read,
+        ),
+        (
+            "Loading the kernel",
+            timestamps.kernel_read,
+            timestamps.segments_copied,
+        ),
+        (
+            "Exiting boot services",
+            timestamps.segments_copied,
+            timestamps.boot_services_exited,
+        ),
+        (
+            "Entering the kernel",
+            timestamps.boot_services_exited,
+            kernel_entry,
+        ),
+    ];
+    println!("Boot time:");
+    for &(step, start, end) in steps.iter() {
+        // A bootloader that didn't record a step leaves it at 0
+        if end == 0 || end < start {
+            println!("  {:<26} unknown", step);
+        } else {
+            println!("  {:<26} {}", step, Ticks(end - start));
+        }
+    }
+    println!("  {:<26} {}", "Total", Ticks(kernel_entry));
+}
+
+/// Formats a number of TSC ticks as milliseconds if possible
+struct Ticks(u64);
+
+impl fmt::Display for Ticks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match tsc::ticks_to_micros(self.0) {
+            Some(micros) => write!(f, "{:>6}.{:03} ms", micros / 1000, micros % 1000),
+            None => write!(f, "{} ticks", self.0),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use conquer_once::spin::OnceCell;
+
+use crate::println;
+
+static BOOTLOADER_LOG: OnceCell<&'static str> = OnceCell::uninit();
+
+/// Keeps the bootloader's log from the boot info around, so it can be shown any time
+pub fn init(log: &'static str) {
+    BOOTLOADER_LOG.init_once(|| log);
+}
+
+/// Prints everything the bootloader logged, with every line marked as coming from it
+pub fn show() {
+    let log = BOOTLOADER_LOG.get().copied().unwrap_or("");
+    if log.is_empty() {
+        println!("bootloader: no messages");
+    }
+    for line in log.lines() {
+        println!("bootloader: {}", line);
+    }
+}
@@ -0,0 +1,93 @@
+use conquer_once::spin::OnceCell;
+
+static CMDLINE: OnceCell<&'static str> = OnceCell::uninit();
+
+/// One option of the command line, either `key=value` or just `key`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CmdlineOption<'a> {
+    pub key: &'a str,
+    pub value: Option<&'a str>,
+}
+
+/// Splits a command line into options separated by whitespace
+/// Values can be put in double quotes to include spaces, like `init="/bin/sh -x"`
+#[derive(Debug, Clone)]
+pub struct Options<'a> {
+    rest: &'a str,
+}
+
+/// Stores the command line the bootloader passed in, so options can be read from anywhere
+pub fn init(cmdline: &'static str) {
+    CMDLINE.init_once(|| cmdline);
+}
+
+/// The whole command line, empty before `init` is called
+pub fn cmdline() -> &'static str {
+    CMDLINE.get().copied().unwrap_or("")
+}
+
+/// All the options in the order they were given
+pub fn options() -> Options<'static> {
+    Options::new(cmdline())
+}
+
+/// The value of an option like `console=serial`, the last one wins if it was given more than once
+pub fn get(key: &str) -> Option<&'static str> {
+    options()
+        .filter(|option| option.key == key)
+        .filter_map(|option| option.value)
+        .last()
+}
+
+/// Whether an option was given at all, with or without a value
+pub fn contains(key: &str) -> bool {
+    options().any(|option| option.key == key)
+}
+
+impl<'a> Options<'a> {
+    pub fn new(cmdline: &'a str) -> Self {
+        Self { rest: cmdline }
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = CmdlineOption<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            self.rest = rest;
+            return None;
+        }
+        // The option ends at the first whitespace that isn't inside of quotes
+        let mut in_quotes = false;
+        let mut end = rest.len();
+        for (i, c) in rest.char_indices() {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            } else if c.is_whitespace() && !in_quotes {
+                end = i;
+                break;
+            }
+        }
+        let option = &rest[..end];
+        self.rest = &rest[end..];
+        Some(match option.split_once('=') {
+            Some((key, value)) => CmdlineOption {
+                key,
+                value: Some(unquote(value)),
+            },
+            None => CmdlineOption {
+                key: option,
+                value: None,
+            },
+        })
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    match value.strip_prefix('"') {
+        Some(value) => value.strip_suffix('"').unwrap_or(value),
+        None => value,
+    }
+}
@@ -0,0 +1,123 @@
+use alloc::string::String;
+use core::{hint, str};
+
+use super::initrd::{self, EntryKind};
+use super::serial::SERIAL;
+use super::{boot_time, bootlog};
+use crate::{println, serial_print};
+
+/// Something that can be typed on the serial port once the kernel is done booting
+struct Command {
+    name: &'static str,
+    /// Shown by `help`
+    description: &'static str,
+    /// Gets the rest of the line after the name
+    run: fn(&str),
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "boottime",
+        description: "show how long each step of booting took",
+        run: |_| boot_time::show(),
+    },
+    Command {
+        name: "bootlog",
+        description: "show what the bootloader logged",
+        run: |_| bootlog::show(),
+    },
+    Command {
+        name: "ls",
+        description: "list the files in the initrd",
+        run: ls,
+    },
+    Command {
+        name: "cat",
+        description: "show a text file from the initrd",
+        run: cat,
+    },
+    Command {
+        name: "help",
+        description: "list the commands",
+        run: |_| help(),
+    },
+];
+
+/// Reads commands from the serial port, one per line, and runs them forever
+///
+/// The port is polled since there are no device interrupts yet.
+/// Command output goes to the kernel console, typed characters are echoed on the serial port
+pub fn run() -> ! {
+    println!("Type help on the serial port for a list of commands");
+    serial_print!("> ");
+    let mut line = String::new();
+    loop {
+        let byte = SERIAL.lock().receive();
+        match byte {
+            Some(b'\r') | Some(b'\n') => {
+                serial_print!("\n");
+                execute(line.trim());
+                line.clear();
+                serial_print!("> ");
+            }
+            // Backspace and delete
+            Some(0x08) | Some(0x7F) => {
+                if line.pop().is_some() {
+                    serial_print!("\x08 \x08");
+                }
+            }
+            Some(byte) if byte == b' ' || byte.is_ascii_graphic() => {
+                line.push(byte as char);
+                serial_print!("{}", byte as char);
+            }
+            Some(_) => {}
+            None => hint::spin_loop(),
+        }
+    }
+}
+
+fn execute(line: &str) {
+    if line.is_empty() {
+        return;
+    }
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+    match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => (command.run)(args.trim()),
+        None => println!("Unknown command {}, try help", name),
+    }
+}
+
+fn help() {
+    for command in COMMANDS {
+        println!("  {:<10} {}", command.name, command.description);
+    }
+}
+
+fn ls(_args: &str) {
+    let archive = match initrd::archive() {
+        Some(archive) => archive,
+        None => {
+            println!("There is no initrd");
+            return;
+        }
+    };
+    for entry in archive.entries() {
+        match entry.kind {
+            EntryKind::Directory => println!("  {}/", entry.name),
+            EntryKind::Symlink => println!("  {} (link)", entry.name),
+            _ => println!("  {:<40} {} bytes", entry.name, entry.data.len()),
+        }
+    }
+}
+
+fn cat(path: &str) {
+    if path.is_empty() {
+        println!("Usage: cat <path>");
+        return;
+    }
+    match initrd::read(path).map(str::from_utf8) {
+        Some(Ok(text)) => println!("{}", text),
+        Some(Err(_)) => println!("{} isn't a text file", path),
+        None => println!("There is no file {} in the initrd", path),
+    }
+}
@@ -1,7 +1,10 @@
+use boot_info::FramebufferInfo;
 use conquer_once::spin::OnceCell;
 use core::{fmt, ptr};
 use spin::Mutex;
 
+use super::serial;
+
 static FONT: &[u8] = include_bytes!("../font.psf");
 #[repr(packed)]
 #[allow(dead_code)]
@@ -16,29 +19,17 @@ struct PsfHeader {
     width: u32,         /* width in pixels */
 }
 
-#[repr(C)]
-struct FramebufferRes {
-    x: u64,
-    y: u64,
-}
-
-/// This struct is passed to the kernel by the bootloader
-/// and contains all the necessary information to use the GOP framebuffer
-#[repr(C)]
-pub struct FramebufferInfo {
-    pointer: *mut u32,
-    size: u64,
-    resolution: FramebufferRes,
-    stride: u64,
-}
-
 /// The FramebufferInfo struct can be turned into this, which has implementations for writing text
 pub struct Framebuffer {
     info: FramebufferInfo,
     current_line: usize,
     current_col: usize,
+    /// Text color encoded in the framebuffer's pixel format
+    text_color: u32,
 }
-unsafe impl Send for Framebuffer {}
+
+/// Red, green and blue of the text
+const TEXT_COLOR: (u8, u8, u8) = (0, 0, 255);
 
 pub static FRAMEBUFFER: OnceCell<Mutex<Framebuffer>> = OnceCell::uninit();
 
@@ -63,11 +54,14 @@ macro_rules! println {
 }
 
 /// Just a wrapper to call the global framebuffer write_fmt
-/// Used by the print! and println! macros
+/// Used by the print! and println! macros, which go to the serial port without a framebuffer
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    FRAMEBUFFER.get().unwrap().lock().write_fmt(args).unwrap();
+    match FRAMEBUFFER.get() {
+        Some(fb) => fb.lock().write_fmt(args).unwrap(),
+        None => serial::_print(args),
+    }
 }
 
 impl Framebuffer {
@@ -77,16 +71,17 @@ impl Framebuffer {
             info: fb_info,
             current_line: 0,
             current_col: 0,
+            text_color: encode_color(&fb_info, TEXT_COLOR),
         }
     }
     /// Print a string to the current line and col positions, auto wraps
+    /// and scrolls up once the bottom of the screen is reached
     pub fn print(&mut self, text: &str) {
         let (header_size, bytes_per_glyph, height, width) = get_font_info();
         for c in text.bytes() {
             // If text overflows line or if character is a newline, move down a line
             if c == b'\n' || self.current_col as u64 + width > self.info.stride {
-                self.current_line += 1;
-                self.current_col = 0;
+                self.new_line(height);
                 // Don't write char if it's a newline
                 if c == b'\n' {
                     continue;
@@ -114,6 +109,27 @@ impl Framebuffer {
             self.current_col += (width + 1) as usize;
         }
     }
+
+    /// Moves to the start of the next line, at the bottom everything moves up by one line instead
+    fn new_line(&mut self, glyph_height: u64) {
+        self.current_col = 0;
+        let lines = (self.info.height / glyph_height) as usize;
+        if self.current_line + 1 < lines {
+            self.current_line += 1;
+            return;
+        }
+        if lines == 0 {
+            return;
+        }
+        let line_pixels = (glyph_height * self.info.stride) as usize;
+        let kept_pixels = (lines - 1) * line_pixels;
+        let base = self.info.base as *mut u32;
+        unsafe {
+            ptr::copy(base.add(line_pixels), base, kept_pixels);
+            ptr::write_bytes(base.add(kept_pixels), 0, line_pixels);
+        }
+    }
+
     pub fn _draw_rect(&self, x: u64, y: u64, width: u64, height: u64) {
         let mut cursor = (x, y);
         loop {
@@ -129,13 +145,13 @@ impl Framebuffer {
         }
     }
 
-    /// Draws a point at the specified x/y location, no support for colors (yet)
+    /// Draws a point in the text color at the specified x/y location
     pub fn draw_point(&self, x: u64, y: u64) {
         let fb = &self.info;
         let offset = x + y * fb.stride;
-        if offset < fb.size {
+        if offset < fb.pixel_count() {
             unsafe {
-                ptr::write(fb.pointer.add(offset as usize), 255);
+                ptr::write((fb.base as *mut u32).add(offset as usize), self.text_color);
             }
         }
     }
@@ -143,11 +159,28 @@ impl Framebuffer {
     /// Clears out the framebuffer by writing it all to 0s
     pub fn clear(&self) {
         unsafe {
-            ptr::write_bytes(self.info.pointer, 0, self.info.size as usize);
+            ptr::write_bytes(self.info.base as *mut u8, 0, self.info.size as usize);
         }
     }
 }
 
+/// Turns a color into the pixel value for the framebuffer's format
+/// Every channel is scaled to the number of bits in its mask
+fn encode_color(fb: &FramebufferInfo, (red, green, blue): (u8, u8, u8)) -> u32 {
+    encode_channel(red, fb.masks.red)
+        | encode_channel(green, fb.masks.green)
+        | encode_channel(blue, fb.masks.blue)
+}
+
+fn encode_channel(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    ((value as u64 * max / 255) as u32) << shift
+}
+
 /// Gets info about the fonts from the PSF header
 /// Supports PSF v1 and v2
 fn get_font_info() -> (usize, usize, u64, u64) {
@@ -0,0 +1,39 @@
+use boot_info::BootInfo;
+use conquer_once::spin::OnceCell;
+use core::slice;
+use x86_64::PhysAddr;
+
+use super::memory::phys_to_virt;
+
+mod tar;
+pub use tar::{EntryKind, TarArchive};
+
+static INITRD: OnceCell<TarArchive<'static>> = OnceCell::uninit();
+
+/// Makes the initial ramdisk from the boot info available as the first filesystem
+/// Must be called after `memory::init`, returns false if the bootloader didn't load one
+pub fn init(boot_info: &'static BootInfo) -> bool {
+    let range = boot_info.initrd;
+    if range.is_empty() {
+        return false;
+    }
+    // The bootloader allocated it with its own memory type, so the frames are never reused
+    let data = unsafe {
+        slice::from_raw_parts(
+            phys_to_virt(PhysAddr::new(range.start)).as_ptr::<u8>(),
+            range.len() as usize,
+        )
+    };
+    INITRD.init_once(|| TarArchive::new(data));
+    true
+}
+
+/// The initial ramdisk, if the bootloader loaded one
+pub fn archive() -> Option<&'static TarArchive<'static>> {
+    INITRD.get()
+}
+
+/// Reads a whole file from the initial ramdisk
+pub fn read(path: &str) -> Option<&'static [u8]> {
+    archive()?.find(path).map(|entry| entry.data)
+}
@@ -0,0 +1,123 @@
+use core::str;
+
+/// Tar archives are made of 512 byte blocks
+const BLOCK_SIZE: usize = 512;
+
+/// A read-only view of a tar archive in memory, in the ustar or old v7 format
+#[derive(Debug, Clone, Copy)]
+pub struct TarArchive<'a> {
+    data: &'a [u8],
+}
+
+/// What kind of file an entry is, from the type flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    /// Hard links, devices, fifos and anything else
+    Other(u8),
+}
+
+/// A file in the archive
+#[derive(Debug, Clone, Copy)]
+pub struct TarEntry<'a> {
+    /// Full path without a leading `./` or `/`
+    /// The ustar prefix is not joined on, paths over 100 bytes are cut off at the prefix
+    pub name: &'a str,
+    pub kind: EntryKind,
+    pub data: &'a [u8],
+}
+
+impl<'a> TarArchive<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// All entries in the order they are stored in
+    /// Stops at the end of archive marker or at the first header that isn't valid
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            data: self.data,
+            offset: 0,
+        }
+    }
+
+    /// Finds a regular file by path, `./` or `/` in front of it is ignored
+    pub fn find(&self, path: &str) -> Option<TarEntry<'a>> {
+        let path = normalize(path);
+        self.entries()
+            .find(|entry| entry.kind == EntryKind::File && entry.name == path)
+    }
+}
+
+pub struct Entries<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = TarEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.data.get(self.offset..self.offset + BLOCK_SIZE)?;
+        // The archive ends with two zero blocks, but one is enough to stop
+        if header.iter().all(|&byte| byte == 0) || !checksum_valid(header) {
+            return None;
+        }
+        let size = parse_octal(&header[124..136])?;
+        let data_start = self.offset + BLOCK_SIZE;
+        let data = self.data.get(data_start..data_start.checked_add(size)?)?;
+        // The data is padded to a whole block
+        self.offset = data_start + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+
+        let name = str::from_utf8(until_nul(&header[0..100])).ok()?;
+        let kind = match header[156] {
+            b'0' | 0 => EntryKind::File,
+            b'5' => EntryKind::Directory,
+            b'2' => EntryKind::Symlink,
+            other => EntryKind::Other(other),
+        };
+        Some(TarEntry {
+            name: normalize(name),
+            kind,
+            data,
+        })
+    }
+}
+
+/// The checksum is the sum of all header bytes, with the checksum field itself as spaces
+fn checksum_valid(header: &[u8]) -> bool {
+    let expected = match parse_octal(&header[148..156]) {
+        Some(checksum) => checksum,
+        None => return false,
+    };
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { b' ' } else { byte } as usize)
+        .sum();
+    sum == expected
+}
+
+/// Numbers are stored as octal text, padded with spaces or nul bytes
+fn parse_octal(field: &[u8]) -> Option<usize> {
+    let text = str::from_utf8(until_nul(field)).ok()?.trim();
+    if text.is_empty() {
+        return Some(0);
+    }
+    usize::from_str_radix(text, 8).ok()
+}
+
+fn until_nul(field: &[u8]) -> &[u8] {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    &field[..end]
+}
+
+fn normalize(path: &str) -> &str {
+    let path = path.strip_prefix("./").unwrap_or(path);
+    path.trim_start_matches('/')
+}
@@ -0,0 +1,218 @@
+use boot_info::{MemoryRegion, MemoryRegionKind, PhysicalRange};
+use core::slice;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+use super::phys_to_virt;
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = 64;
+
+/// Keeps track of every physical frame with one bit, set if the frame is in use
+///
+/// The bitmap itself is stored in the first usable memory region big enough to hold it
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    /// Number of frames the bitmap covers, starting at physical address 0
+    frame_count: usize,
+    /// Number of frames that were usable when the allocator was created
+    usable_frames: usize,
+    free_frames: usize,
+    /// The most frames that were ever in use at the same time
+    peak_used: usize,
+    /// Where to start looking for a free frame, everything below it is most likely in use
+    next_free: usize,
+}
+
+/// Usage statistics of the frame allocator, all counts are in frames
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    pub used: usize,
+    pub peak_used: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Creates the allocator with every usable frame in the memory map marked as free,
+    /// except for the ones overlapping `excluded`
+    ///
+    /// # Safety
+    /// The memory map has to be correct and all of physical memory has to be mapped
+    /// at the physical memory offset, since the bitmap is written through it
+    pub unsafe fn new(regions: &[MemoryRegion], excluded: &[PhysicalRange]) -> Option<Self> {
+        let usable = || {
+            regions
+                .iter()
+                .filter(|region| region.kind == MemoryRegionKind::Usable)
+        };
+        let highest_address = usable().map(|region| region.end()).max()?;
+        let frame_count = (highest_address / FRAME_SIZE) as usize;
+        let word_count = (frame_count + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        let bitmap_size = align_up(word_count as u64 * 8);
+
+        let bitmap_start =
+            usable().find_map(|region| find_free_range(region, bitmap_size, excluded))?;
+        let bitmap_ptr = phys_to_virt(PhysAddr::new(bitmap_start)).as_mut_ptr::<u64>();
+        let bitmap = slice::from_raw_parts_mut(bitmap_ptr, word_count);
+
+        // Start with everything in use, then free the usable regions
+        bitmap.fill(u64::MAX);
+        let mut allocator = Self {
+            bitmap,
+            frame_count,
+            usable_frames: 0,
+            free_frames: 0,
+            peak_used: 0,
+            next_free: 0,
+        };
+        for region in usable() {
+            allocator.set_range(region.start, region.end(), false);
+        }
+        let bitmap_range = PhysicalRange {
+            start: bitmap_start,
+            end: bitmap_start + bitmap_size,
+        };
+        for range in excluded.iter().chain(Some(&bitmap_range)) {
+            allocator.set_range(range.start, range.end, true);
+        }
+        // Never hand out the frame at address 0, it would look like a null pointer
+        allocator.set_range(0, FRAME_SIZE, true);
+        allocator.usable_frames = allocator.free_frames;
+        Some(allocator)
+    }
+
+    /// Allocates `count` physically contiguous frames and returns the first one
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        if count == 0 || count > self.free_frames {
+            return None;
+        }
+        let mut start = self.next_free;
+        let mut found = 0;
+        let mut frame = start;
+        while frame < self.frame_count {
+            if self.is_used(frame) {
+                found = 0;
+                start = frame + 1;
+            } else {
+                found += 1;
+                if found == count {
+                    for index in start..start + count {
+                        self.set_used(index, true);
+                    }
+                    if start == self.next_free {
+                        self.next_free = start + count;
+                    }
+                    return Some(frame_at(start));
+                }
+            }
+            frame += 1;
+        }
+        // Frames below the hint might have been freed since
+        if self.next_free != 0 {
+            self.next_free = 0;
+            return self.allocate_contiguous(count);
+        }
+        None
+    }
+
+    /// Frees `count` frames starting at `frame`, previously returned by `allocate_contiguous`
+    /// Frames past the end of the bitmap were never handed out, so they are ignored
+    ///
+    /// # Safety
+    /// The frames must not be used anymore
+    pub unsafe fn deallocate_contiguous(&mut self, frame: PhysFrame, count: usize) {
+        let start = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        let end = start.saturating_add(count);
+        debug_assert!(
+            end <= self.frame_count,
+            "Freeing frames {} to {} which were never allocated",
+            start,
+            end
+        );
+        for index in start..end.min(self.frame_count) {
+            debug_assert!(
+                self.is_used(index),
+                "Double free of frame {:?}",
+                frame_at(index)
+            );
+            self.set_used(index, false);
+        }
+        self.next_free = self.next_free.min(start);
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.usable_frames,
+            free: self.free_frames,
+            used: self.usable_frames - self.free_frames,
+            peak_used: self.peak_used,
+        }
+    }
+
+    /// Marks every frame overlapping the physical address range as used or free
+    fn set_range(&mut self, start: u64, end: u64, used: bool) {
+        let first = (start / FRAME_SIZE) as usize;
+        let last = ((align_up(end) / FRAME_SIZE) as usize).min(self.frame_count);
+        for index in first..last {
+            self.set_used(index, used);
+        }
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, index: usize, used: bool) {
+        if self.is_used(index) == used {
+            return;
+        }
+        let word = &mut self.bitmap[index / BITS_PER_WORD];
+        if used {
+            *word |= 1 << (index % BITS_PER_WORD);
+            self.free_frames -= 1;
+        } else {
+            *word &= !(1 << (index % BITS_PER_WORD));
+            self.free_frames += 1;
+        }
+        let used_frames = self.usable_frames.saturating_sub(self.free_frames);
+        self.peak_used = self.peak_used.max(used_frames);
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_contiguous(1)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate_contiguous(frame, 1)
+    }
+}
+
+/// Finds `size` bytes in the region that don't overlap any excluded range
+fn find_free_range(region: &MemoryRegion, size: u64, excluded: &[PhysicalRange]) -> Option<u64> {
+    // Skip the first frame since it will never be handed out anyway
+    let mut start = region.start.max(FRAME_SIZE);
+    while start + size <= region.end() {
+        let end = start + size;
+        match excluded
+            .iter()
+            .find(|range| range.start < end && start < range.end)
+        {
+            Some(range) => start = align_up(range.end),
+            None => return Some(start),
+        }
+    }
+    None
+}
+
+fn frame_at(index: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+}
+
+fn align_up(address: u64) -> u64 {
+    (address + FRAME_SIZE - 1) & !(FRAME_SIZE - 1)
+}
@@ -0,0 +1,60 @@
+use boot_info::{BootInfo, PhysicalRange};
+use conquer_once::spin::OnceCell;
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+
+mod frame_allocator;
+pub use frame_allocator::{BitmapFrameAllocator, FrameStats};
+
+/// Where all of physical memory is mapped in the kernel's address space
+/// The bootloader picks it and passes it in the boot info
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+pub static FRAME_ALLOCATOR: OnceCell<Mutex<BitmapFrameAllocator>> = OnceCell::uninit();
+
+/// Returns the virtual address physical memory can be accessed at
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(addr.as_u64() + PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// Returns the virtual address that physical address 0 is mapped to
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// Sets up the global frame allocator from the memory map in the boot info
+/// and has to be called before anything uses the physical memory mapping
+/// The kernel image, the framebuffer and the boot info itself are never handed out
+pub fn init(boot_info: &'static BootInfo) {
+    let offset = boot_info.physical_memory_offset;
+    PHYSICAL_MEMORY_OFFSET.store(offset, Ordering::Relaxed);
+    let regions = boot_info.memory_map.regions();
+    // Both are accessed through the physical memory mapping
+    let boot_info_start = boot_info as *const BootInfo as u64 - offset;
+    let regions_start = regions.as_ptr() as u64 - offset;
+    let excluded = [
+        boot_info.kernel_image,
+        PhysicalRange {
+            start: boot_info.framebuffer.physical_base,
+            end: boot_info.framebuffer.physical_base + boot_info.framebuffer.size,
+        },
+        PhysicalRange {
+            start: boot_info_start,
+            end: boot_info_start + mem::size_of::<BootInfo>() as u64,
+        },
+        PhysicalRange {
+            start: regions_start,
+            end: regions_start + mem::size_of_val(regions) as u64,
+        },
+    ];
+    let allocator = unsafe { BitmapFrameAllocator::new(regions, &excluded) }
+        .expect("No usable memory for the frame allocator");
+    FRAME_ALLOCATOR.init_once(move || Mutex::new(allocator));
+}
+
+/// Returns the usage statistics of the global frame allocator
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR.get().unwrap().lock().stats()
+}
@@ -1,3 +1,23 @@
+pub mod boot_time;
+
+pub mod bootlog;
+
+pub mod allocator;
+
+pub mod cmdline;
+
+pub mod console;
+
 pub mod framebuffer;
 
+pub mod initrd;
+
 pub mod interrupts;
+
+pub mod memory;
+
+pub mod paging;
+
+pub mod serial;
+
+pub mod tsc;
@@ -0,0 +1,176 @@
+use bitflags::bitflags;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{MapToError, Translate, UnmapError};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::memory::{phys_to_virt, physical_memory_offset};
+
+bitflags! {
+    /// Permissions of a mapped page, it is always readable and present
+    pub struct MapFlags: u8 {
+        const WRITABLE = 1 << 0;
+        const NO_EXECUTE = 1 << 1;
+        const USER = 1 << 2;
+    }
+}
+
+/// How the CPU caches accesses to a page, selected with the PWT and PCD bits
+/// This assumes the default PAT layout set up at reset, which the firmware leaves alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum CacheMode {
+    /// Normal memory
+    WriteBack,
+    /// Reads are cached but writes go straight to memory, good for framebuffers
+    WriteThrough,
+    /// Nothing is cached, needed for device registers
+    Uncached,
+}
+
+/// A 4-level page table hierarchy, identified by the physical frame of its PML4
+///
+/// All page tables are accessed through the physical memory mapping,
+/// so this works for address spaces that aren't active too
+pub struct AddressSpace {
+    pml4: PhysFrame,
+}
+
+impl AddressSpace {
+    /// The address space that is currently loaded in CR3
+    pub fn active() -> Self {
+        Self {
+            pml4: Cr3::read().0,
+        }
+    }
+
+    /// Creates an empty address space that shares the kernel's mappings
+    ///
+    /// Only the higher half of the PML4 is copied, so changes to the lower level tables
+    /// of the kernel show up in every address space, and the lower half starts out empty
+    #[allow(dead_code)]
+    pub fn new(allocator: &mut impl FrameAllocator<Size4KiB>) -> Option<Self> {
+        let pml4 = allocator.allocate_frame()?;
+        let space = Self { pml4 };
+        let active = Self::active();
+        unsafe {
+            let table = &mut *space.table_ptr();
+            let active_table = &*active.table_ptr();
+            for (i, entry) in table.iter_mut().enumerate() {
+                if i < 256 {
+                    entry.set_unused();
+                } else {
+                    *entry = active_table[i].clone();
+                }
+            }
+        }
+        Some(space)
+    }
+
+    /// Physical frame of the PML4, the value to load into CR3
+    #[allow(dead_code)]
+    pub fn pml4_frame(&self) -> PhysFrame {
+        self.pml4
+    }
+
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.pml4
+    }
+
+    /// Loads this address space into CR3
+    ///
+    /// # Safety
+    /// Everything the kernel is currently using (code, stack, data) has to be mapped
+    /// at the same addresses in this address space
+    #[allow(dead_code)]
+    pub unsafe fn switch_to(&self) {
+        let (_, flags) = Cr3::read();
+        Cr3::write(self.pml4, flags);
+    }
+
+    /// Maps `page` to `frame`, allocating any missing page tables from `allocator`
+    ///
+    /// # Safety
+    /// Mapping a frame that is already in use somewhere else can break memory safety
+    pub unsafe fn map(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: MapFlags,
+        cache: CacheMode,
+        allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let mut table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        if flags.contains(MapFlags::USER) {
+            table_flags |= PageTableFlags::USER_ACCESSIBLE;
+        }
+        let is_active = self.is_active();
+        let flush = self.mapper().map_to_with_table_flags(
+            page,
+            frame,
+            page_table_flags(flags, cache),
+            table_flags,
+            allocator,
+        )?;
+        // The TLB only holds entries for the active address space
+        if is_active {
+            flush.flush();
+        } else {
+            flush.ignore();
+        }
+        Ok(())
+    }
+
+    /// Removes the mapping of `page` and returns the frame it was mapped to
+    /// The frame isn't freed, that's up to the caller
+    pub fn unmap(&mut self, page: Page) -> Result<PhysFrame, UnmapError> {
+        let is_active = self.is_active();
+        let (frame, flush) = unsafe { self.mapper() }.unmap(page)?;
+        if is_active {
+            flush.flush();
+        } else {
+            flush.ignore();
+        }
+        Ok(frame)
+    }
+
+    /// Returns the physical address a virtual address is mapped to, huge pages included
+    #[allow(dead_code)]
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        unsafe { self.mapper() }.translate_addr(addr)
+    }
+
+    /// # Safety
+    /// Only one mapper for the same address space may be used at a time
+    unsafe fn mapper(&self) -> OffsetPageTable<'static> {
+        OffsetPageTable::new(&mut *self.table_ptr(), physical_memory_offset())
+    }
+
+    fn table_ptr(&self) -> *mut PageTable {
+        phys_to_virt(self.pml4.start_address()).as_mut_ptr()
+    }
+}
+
+/// Converts the flags to the bits in a page table entry
+fn page_table_flags(flags: MapFlags, cache: CacheMode) -> PageTableFlags {
+    let mut entry_flags = PageTableFlags::PRESENT;
+    if flags.contains(MapFlags::WRITABLE) {
+        entry_flags |= PageTableFlags::WRITABLE;
+    }
+    if flags.contains(MapFlags::NO_EXECUTE) {
+        entry_flags |= PageTableFlags::NO_EXECUTE;
+    }
+    if flags.contains(MapFlags::USER) {
+        entry_flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+    match cache {
+        CacheMode::WriteBack => {}
+        CacheMode::WriteThrough => entry_flags |= PageTableFlags::WRITE_THROUGH,
+        CacheMode::Uncached => {
+            entry_flags |= PageTableFlags::WRITE_THROUGH | PageTableFlags::NO_CACHE
+        }
+    }
+    entry_flags
+}
@@ -0,0 +1,105 @@
+use conquer_once::spin::Lazy;
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+/// I/O port of the first serial port (COM1)
+const COM1: u16 = 0x3F8;
+
+/// A 16550 UART, used for output before (or instead of) the framebuffer
+pub struct SerialPort {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: Port<u8>,
+}
+
+pub static SERIAL: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
+    let mut serial = SerialPort::new(COM1);
+    serial.init();
+    Mutex::new(serial)
+});
+
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => ($crate::utils::serial::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Just a wrapper to call the global serial port write_fmt
+/// Used by the serial_print! and serial_println! macros
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    SERIAL.lock().write_fmt(args).unwrap();
+}
+
+impl SerialPort {
+    pub fn new(base: u16) -> Self {
+        Self {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: Port::new(base + 5),
+        }
+    }
+
+    /// Sets the port up for 38400 baud, 8 data bits, no parity and one stop bit
+    pub fn init(&mut self) {
+        unsafe {
+            // Disable interrupts, we only poll
+            self.interrupt_enable.write(0x00);
+            // Enable DLAB so the baud rate divisor can be set
+            self.line_control.write(0x80);
+            // Divisor 3 (low byte, then high byte) = 38400 baud
+            self.data.write(0x03);
+            self.interrupt_enable.write(0x00);
+            // Disable DLAB, 8 bits, no parity, one stop bit
+            self.line_control.write(0x03);
+            // Enable and clear the FIFOs with a 14 byte threshold
+            self.fifo_control.write(0xC7);
+            // Set DTR, RTS and OUT2
+            self.modem_control.write(0x0B);
+        }
+    }
+
+    /// Waits until the transmit buffer is empty and sends one byte
+    pub fn send(&mut self, byte: u8) {
+        unsafe {
+            while self.line_status.read() & 0x20 == 0 {}
+            self.data.write(byte);
+        }
+    }
+
+    /// Returns the next received byte, or `None` if nothing arrived
+    pub fn receive(&mut self) -> Option<u8> {
+        unsafe {
+            if self.line_status.read() & 0x01 == 0 {
+                return None;
+            }
+            Some(self.data.read())
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // Terminals expect a carriage return before every newline
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
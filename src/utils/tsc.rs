@@ -0,0 +1,63 @@
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+/// Frequency of the programmable interval timer's input clock in Hz
+const PIT_FREQUENCY: u64 = 1_193_182;
+/// How long calibration measures for, in milliseconds
+const CALIBRATION_MS: u64 = 10;
+/// Calibration gives up after this many TSC ticks, in case there is no PIT
+const CALIBRATION_TIMEOUT: u64 = 10_000_000_000;
+
+/// TSC ticks per second, 0 until the TSC is calibrated
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// The current value of the time stamp counter
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Measures how fast the TSC runs by counting its ticks while the PIT counts down
+/// and returns the frequency in Hz, or 0 if the PIT didn't respond
+///
+/// PIT channel 2 is used since its output can be polled through port 0x61,
+/// so this works without interrupts
+pub fn calibrate() -> u64 {
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel2 = Port::<u8>::new(0x42);
+    let count = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+    let (start, end) = unsafe {
+        // Enable the gate of channel 2 and disconnect it from the speaker
+        let value = gate.read();
+        gate.write((value & !0x02) | 0x01);
+        // Channel 2, low byte then high byte, mode 0 (interrupt on terminal count), binary
+        command.write(0b1011_0000);
+        channel2.write(count as u8);
+        channel2.write((count >> 8) as u8);
+        let start = read();
+        // Bit 5 is the output of channel 2, which goes high once the count reaches 0
+        while gate.read() & 0x20 == 0 {
+            if read() - start > CALIBRATION_TIMEOUT {
+                return 0;
+            }
+        }
+        (start, read())
+    };
+    let frequency = (end - start) * 1000 / CALIBRATION_MS;
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+    frequency
+}
+
+/// TSC ticks per second, 0 before `calibrate` was called
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Converts a number of TSC ticks to microseconds, `None` if the TSC isn't calibrated
+pub fn ticks_to_micros(ticks: u64) -> Option<u64> {
+    match frequency() {
+        0 => None,
+        frequency => Some((ticks as u128 * 1_000_000 / frequency as u128) as u64),
+    }
+}